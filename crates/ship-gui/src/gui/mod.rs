@@ -8,6 +8,7 @@ use iced::window::icon;
 use iced::{subscription, window, Application, Command, Element, Renderer, Settings, Subscription};
 use ship_internal::application::app::AppManager;
 use ship_internal::application::settings::SettingsManager;
+use ship_internal::application::update::history::UpdateRecord;
 use ship_internal::application::update::update_manage::UpdateManager;
 use ship_internal::version::version_manage::VersionManager;
 use ship_internal::{application, version};
@@ -42,6 +43,8 @@ pub struct Gui {
     app_manager: Arc<Mutex<AppManager>>,
     update_manager: Arc<Mutex<UpdateManager>>,
     pub show_about_modal: bool,
+    // app_server_id and its records, newest first
+    pub update_history: Option<(u64, Vec<UpdateRecord>)>,
 }
 
 impl Application for Gui {
@@ -61,6 +64,7 @@ impl Application for Gui {
                 app_manager: flags.app_manager,
                 update_manager: flags.update_manager,
                 show_about_modal: false,
+                update_history: None,
             },
             Command::none(),
         )
@@ -120,9 +124,50 @@ pub enum Message {
 
     SelectApp(u64),
     SelectAppServer(u64, u64),
-    StartUpdate { app_server_id: u64, app_id: u64 },
-    CancelUpdate { app_server_id: u64, app_id: u64 },
-    ClickStart { app_server_id: u64, app_id: u64 },
+    StartUpdate {
+        app_server_id: u64,
+        app_id: u64,
+    },
+    CancelUpdate {
+        app_server_id: u64,
+        app_id: u64,
+    },
+    PauseUpdate {
+        app_server_id: u64,
+    },
+    ResumeUpdate {
+        app_server_id: u64,
+    },
+    DiscardUpdate {
+        app_server_id: u64,
+    },
+    PreviewUpdate {
+        app_server_id: u64,
+    },
+    ConfirmUpdate {
+        app_server_id: u64,
+    },
+    VerifyFiles {
+        app_server_id: u64,
+    },
+    RepairFiles {
+        app_server_id: u64,
+    },
+    DismissUpdateResult {
+        app_server_id: u64,
+    },
+    MoveQueuedUpdate {
+        app_server_id: u64,
+        is_forward: bool,
+    },
+    ShowUpdateHistory {
+        app_server_id: u64,
+    },
+    CloseUpdateHistory,
+    ClickStart {
+        app_server_id: u64,
+        app_id: u64,
+    },
 
     OpenDir(String),
     OpenImage(String),
@@ -132,6 +177,8 @@ pub enum Message {
     SelfUpdateRestart,
 
     ReGenerateCacheDb,
+    ToggleDeepVerify(bool),
+    SetDownloadLimit(u64),
 }
 
 #[derive(Default)]
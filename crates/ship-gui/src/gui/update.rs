@@ -49,6 +49,47 @@ impl Gui {
             Message::CancelUpdate { app_server_id, .. } => {
                 update::stop_update(app_server_id, Arc::clone(&self.update_manager));
             }
+            Message::PauseUpdate { app_server_id } => {
+                update::pause_update(app_server_id, Arc::clone(&self.update_manager));
+            }
+            Message::ResumeUpdate { app_server_id } => {
+                update::resume_update(app_server_id, Arc::clone(&self.update_manager));
+            }
+            Message::DiscardUpdate { app_server_id } => {
+                update::discard_update(app_server_id, Arc::clone(&self.update_manager));
+            }
+            Message::PreviewUpdate { app_server_id } => {
+                update::preview_update(app_server_id, Arc::clone(&self.update_manager));
+            }
+            Message::ConfirmUpdate { app_server_id } => {
+                update::confirm_update(app_server_id, Arc::clone(&self.update_manager));
+            }
+            Message::VerifyFiles { app_server_id } => {
+                update::verify_update(app_server_id, Arc::clone(&self.update_manager));
+            }
+            Message::RepairFiles { app_server_id } => {
+                update::repair_update(app_server_id, Arc::clone(&self.update_manager));
+            }
+            Message::DismissUpdateResult { app_server_id } => {
+                update::dismiss_update_result(app_server_id, Arc::clone(&self.update_manager));
+            }
+            Message::MoveQueuedUpdate {
+                app_server_id,
+                is_forward,
+            } => {
+                update::move_queued_update(
+                    app_server_id,
+                    is_forward,
+                    Arc::clone(&self.update_manager),
+                );
+            }
+            Message::ShowUpdateHistory { app_server_id } => {
+                let records = update::get_update_history(app_server_id);
+                self.update_history = Some((app_server_id, records));
+            }
+            Message::CloseUpdateHistory => {
+                self.update_history = None;
+            }
             Message::ClickStart {
                 app_server_id,
                 app_id,
@@ -82,6 +123,14 @@ impl Gui {
                 // TODO
                 cache::generate_cache_db().unwrap();
             }
+            Message::ToggleDeepVerify(is_checked) => {
+                let mut settings_manager_g = self.settings_manager.lock().unwrap();
+                settings_manager_g.settings.update_settings.is_deep_verify = is_checked;
+                drop(settings_manager_g);
+            }
+            Message::SetDownloadLimit(download_limit) => {
+                update::set_download_limit(download_limit, Arc::clone(&self.settings_manager));
+            }
         }
         Command::none()
     }
@@ -1,4 +1,6 @@
 mod template_a;
+mod update_plan;
+mod verify_report;
 
 use crate::gui::view::app::template_a::make_template_a_page;
 use crate::gui::view::{DEFAULT_PADDING, DEFAULT_SPACING};
@@ -1,3 +1,6 @@
+use crate::gui::view::app::update_plan::make_update_plan_modal;
+use crate::gui::view::app::verify_report::make_verify_report_modal;
+use crate::gui::view::settings::DownloadLimitOption;
 use crate::gui::view::DEFAULT_SPACING;
 use crate::gui::Message;
 use iced::alignment::{Horizontal, Vertical};
@@ -7,6 +10,7 @@ use iced_aw::Card;
 use internationalization::t;
 use ship_internal::application::app::app_server::AppServer;
 use ship_internal::application::app::App;
+use ship_internal::application::update;
 use ship_internal::application::update::update_manage::UpdateManager;
 use ship_internal::application::update::UpdateTaskStatus;
 use std::ops::RangeInclusive;
@@ -29,7 +33,11 @@ pub fn make_template_a_page(
     let mut map_vec: Vec<(&u64, &AppServer)> = app.app_server_info.servers.iter().collect();
     map_vec.sort_by(|a, b| b.1.priority.cmp(&a.1.priority));
     for (_, app_server) in map_vec {
-        let app_server_text = Text::new(app_server.name.clone());
+        let mut app_server_name = app_server.name.clone();
+        if app_server.update_available {
+            app_server_name = format!("{}\n{}", app_server_name, t!("update_available"));
+        }
+        let app_server_text = Text::new(app_server_name);
         let mut app_server_btn = Button::new(app_server_text)
             .on_press(Message::SelectAppServer(app_server.id, app_server.app_id))
             .width(Length::Fill)
@@ -82,6 +90,11 @@ pub fn make_template_a_page(
                 let update_processing = update_manager_g
                     .is_update_processing(app_server.id)
                     .unwrap_or(false);
+                let update_status = update_manager_g
+                    .get_update_task_by_app_server_id(app_server.id)
+                    .map(|x| &x.status);
+                let is_syncing = matches!(update_status, Some(UpdateTaskStatus::Processing { .. }));
+                let is_paused = matches!(update_status, Some(UpdateTaskStatus::Paused));
                 drop(update_manager_g);
 
                 let mut start_btn = Button::new(
@@ -105,6 +118,9 @@ pub fn make_template_a_page(
                 if update_processing {
                     update_text = t!("cancel_update");
                 }
+                if is_paused {
+                    update_text = t!("resume_update");
+                }
 
                 let mut update_btn = Button::new(
                     Text::new(format!("{}{}", "", update_text))
@@ -120,13 +136,80 @@ pub fn make_template_a_page(
                         app_server_id: app_server.id,
                         app_id: app_server.app_id,
                     });
+                } else if is_paused {
+                    update_btn = update_btn.on_press(Message::ResumeUpdate {
+                        app_server_id: app_server.id,
+                    });
                 } else {
                     update_btn = update_btn.on_press(Message::StartUpdate {
                         app_server_id: app_server.id,
                         app_id: app_server.app_id,
                     });
                 }
-                let control_panel = Row::new().spacing(10).push(start_btn).push(update_btn);
+                let mut control_panel = Row::new().spacing(10).push(start_btn).push(update_btn);
+                if is_syncing {
+                    let pause_btn = Button::new(
+                        Text::new(t!("pause_update"))
+                            .horizontal_alignment(Horizontal::Center)
+                            .vertical_alignment(Vertical::Center),
+                    )
+                    .height(40)
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::PauseUpdate {
+                        app_server_id: app_server.id,
+                    });
+                    control_panel = control_panel.push(pause_btn);
+                }
+                if is_paused {
+                    let discard_btn = Button::new(
+                        Text::new(t!("discard_update"))
+                            .horizontal_alignment(Horizontal::Center)
+                            .vertical_alignment(Vertical::Center),
+                    )
+                    .height(40)
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::DiscardUpdate {
+                        app_server_id: app_server.id,
+                    });
+                    control_panel = control_panel.push(discard_btn);
+                }
+                // a preview or verification would take the place of the paused update
+                if !update_processing && !is_paused {
+                    let preview_btn = Button::new(
+                        Text::new(t!("update_preview"))
+                            .horizontal_alignment(Horizontal::Center)
+                            .vertical_alignment(Vertical::Center),
+                    )
+                    .height(40)
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::PreviewUpdate {
+                        app_server_id: app_server.id,
+                    });
+                    let verify_btn = Button::new(
+                        Text::new(t!("verify_files"))
+                            .horizontal_alignment(Horizontal::Center)
+                            .vertical_alignment(Vertical::Center),
+                    )
+                    .height(40)
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::VerifyFiles {
+                        app_server_id: app_server.id,
+                    });
+                    let history_btn = Button::new(
+                        Text::new(t!("update_history"))
+                            .horizontal_alignment(Horizontal::Center)
+                            .vertical_alignment(Vertical::Center),
+                    )
+                    .height(40)
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::ShowUpdateHistory {
+                        app_server_id: app_server.id,
+                    });
+                    control_panel = control_panel
+                        .push(preview_btn)
+                        .push(verify_btn)
+                        .push(history_btn);
+                }
                 let control_c = Container::new(control_panel);
 
                 let mut bar = Row::new().spacing(DEFAULT_SPACING).push(control_c);
@@ -146,6 +229,18 @@ pub fn make_template_a_page(
                     app_server_info_c = app_server_info_c.push(banner_c);
                 }
                 app_server_c = app_server_c.push(bar).push(app_server_info_c);
+                if let Some(update_plan_modal) = make_update_plan_modal(
+                    app_server.id,
+                    app_server.app_id,
+                    Arc::clone(&update_manager),
+                ) {
+                    app_server_c = app_server_c.push(update_plan_modal);
+                }
+                if let Some(verify_report_modal) =
+                    make_verify_report_modal(app_server.id, Arc::clone(&update_manager))
+                {
+                    app_server_c = app_server_c.push(verify_report_modal);
+                }
 
                 app_servers_c = app_servers_c.push(app_server_c);
             }
@@ -171,6 +266,7 @@ fn make_progress_bar(
     update_manager: Arc<Mutex<UpdateManager>>,
 ) -> Option<Container<'static, Message>> {
     let mut update_manager_g = update_manager.lock().unwrap();
+    let queue_position = update_manager_g.get_queue_position(app_server_id);
     let update_task_o = update_manager_g.get_mut_update_task_by_app_server_id(app_server_id);
     match update_task_o {
         None => {}
@@ -178,48 +274,181 @@ fn make_progress_bar(
             let tip;
             let mut total = 0;
             let mut value = 0;
+            let mut data_node_tip = "".to_string();
             match &update_task.status {
-                UpdateTaskStatus::Wait => {
-                    tip = format!("{}", t!("update_tip_wait"));
-                }
+                UpdateTaskStatus::Wait => match queue_position {
+                    Some((position, len)) => {
+                        tip = format!("{} {}/{}", t!("update_tip_queued"), position, len);
+                    }
+                    None => {
+                        tip = format!("{}", t!("update_tip_wait"));
+                    }
+                },
                 UpdateTaskStatus::GetServerUpdateInfo => {
                     tip = format!("{}", t!("update_tip_get_server_update_info"));
                 }
-                UpdateTaskStatus::GetClientFileInfo => {
-                    tip = format!("{}", t!("update_tip_get_client_file_info"));
+                UpdateTaskStatus::WaitServerScan { waited_secs } => {
+                    tip = format!("{} {}s", t!("update_tip_wait_server_scan"), waited_secs);
+                }
+                UpdateTaskStatus::GetClientFileInfo { progress } => {
+                    tip = format!(
+                        "{} {} {} {}/{}",
+                        t!("update_tip_get_client_file_info"),
+                        progress.scanned_files,
+                        t!("update_tip_files"),
+                        util::convert::file_size::simple_format(progress.hashed_bytes),
+                        util::convert::file_size::simple_format(progress.total_bytes)
+                    );
+                    total = progress.total_bytes;
+                    value = progress.hashed_bytes;
                 }
                 UpdateTaskStatus::Processing {
                     progress,
                     sync_task,
+                    data_node_stats,
                 } => {
                     tip = format!(
-                        "{} {} {}",
+                        "{} {}/{}  {}/s  {} {}  {} {}",
                         t!("update_tip_processing"),
+                        util::convert::file_size::simple_format(progress.value),
+                        util::convert::file_size::simple_format(progress.total),
+                        util::convert::file_size::simple_format(progress.speed),
+                        t!("update_tip_remaining_time"),
+                        progress
+                            .remaining_time
+                            .map(util::time::format_duration)
+                            .unwrap_or("-".to_string()),
                         util::convert::file_size::simple_format(sync_task.file_info.size),
                         sync_task.file_info.relative_path
                     );
                     total = progress.total;
                     value = progress.value;
+                    data_node_tip = data_node_stats
+                        .iter()
+                        .map(|x| {
+                            let mut s = format!(
+                                "{} {}ms {}/{}",
+                                x.name,
+                                x.latency_ms
+                                    .map(|x| x.to_string())
+                                    .unwrap_or("-".to_string()),
+                                x.success_count,
+                                x.failure_count
+                            );
+                            if x.is_demoted {
+                                s = format!("{} ({})", s, t!("update_tip_data_node_demoted"));
+                            }
+                            s
+                        })
+                        .collect::<Vec<String>>()
+                        .join("  ");
+                    data_node_tip = format!(
+                        "{}: {}  {}",
+                        t!("download_limit"),
+                        DownloadLimitOption(update::get_download_limit()),
+                        data_node_tip
+                    );
+                }
+                UpdateTaskStatus::AwaitingConfirmation { .. } => {
+                    tip = t!("update_tip_awaiting_confirmation").to_string();
+                }
+                UpdateTaskStatus::Planned { .. } => {
+                    tip = t!("update_tip_planned").to_string();
+                }
+                UpdateTaskStatus::Verified { .. } => {
+                    tip = t!("update_tip_verified").to_string();
+                }
+                UpdateTaskStatus::Paused => {
+                    tip = t!("update_tip_paused").to_string();
                 }
                 UpdateTaskStatus::Canceled => {
                     tip = format!("{}", t!("update_tip_canceled"));
                 }
-                UpdateTaskStatus::Failed => {
-                    tip = format!("{}", t!("update_tip_failed"));
-                }
-                UpdateTaskStatus::Finished { finish_time } => {
+                UpdateTaskStatus::Failed { space_shortage } => match space_shortage {
+                    Some(x) => {
+                        let volume = if x.is_cache_volume {
+                            t!("update_tip_cache_volume")
+                        } else {
+                            t!("update_tip_data_volume")
+                        };
+                        tip = format!(
+                            "{}: {} {} ({}), {} {}. {}",
+                            t!("update_tip_insufficient_space"),
+                            volume,
+                            x.path,
+                            util::convert::file_size::simple_format(x.required_bytes),
+                            t!("update_tip_available_space"),
+                            util::convert::file_size::simple_format(x.available_bytes),
+                            t!("update_tip_clean_cache")
+                        );
+                    }
+                    None => {
+                        tip = format!("{}", t!("update_tip_failed"));
+                    }
+                },
+                UpdateTaskStatus::Finished {
+                    finish_time,
+                    protected_files,
+                } => {
                     total = 100; // 100%
                     value = total;
                     let finish_time = util::time::format_timestamp_to_datetime(finish_time.clone());
-                    tip = format!("{} {}", t!("update_tip_finished"), finish_time);
+                    if *protected_files > 0 {
+                        tip = format!(
+                            "{} {}  {} {}",
+                            t!("update_tip_finished"),
+                            finish_time,
+                            protected_files,
+                            t!("update_tip_protected_files")
+                        );
+                    } else {
+                        tip = format!("{} {}", t!("update_tip_finished"), finish_time);
+                    }
                 }
             }
 
             let progress_bar =
                 ProgressBar::new(RangeInclusive::new(0.0, total as f32), value as f32).height(10);
             let progress_tip = Text::new(tip).size(12);
-            let progress_panel = Column::new().push(progress_bar).push(progress_tip);
-            let progress_c = Container::new(progress_panel).max_height(40);
+            let mut progress_panel = Column::new().push(progress_bar);
+            match queue_position {
+                Some((position, len)) => {
+                    let mut move_forward_btn =
+                        Button::new(Text::new(t!("update_queue_forward")).size(12))
+                            .padding([0, 5])
+                            .style(theme::Button::Secondary);
+                    if position > 1 {
+                        move_forward_btn = move_forward_btn.on_press(Message::MoveQueuedUpdate {
+                            app_server_id,
+                            is_forward: true,
+                        });
+                    }
+                    let mut move_backward_btn =
+                        Button::new(Text::new(t!("update_queue_backward")).size(12))
+                            .padding([0, 5])
+                            .style(theme::Button::Secondary);
+                    if position < len {
+                        move_backward_btn = move_backward_btn.on_press(Message::MoveQueuedUpdate {
+                            app_server_id,
+                            is_forward: false,
+                        });
+                    }
+                    progress_panel = progress_panel.push(
+                        Row::new()
+                            .spacing(5)
+                            .push(progress_tip)
+                            .push(move_forward_btn)
+                            .push(move_backward_btn),
+                    );
+                }
+                None => {
+                    progress_panel = progress_panel.push(progress_tip);
+                }
+            }
+            if !data_node_tip.is_empty() {
+                progress_panel = progress_panel.push(Text::new(data_node_tip).size(12));
+            }
+            let progress_c = Container::new(progress_panel).max_height(60);
             return Some(progress_c);
         }
     }
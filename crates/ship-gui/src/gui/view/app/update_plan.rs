@@ -0,0 +1,112 @@
+use crate::gui::Message;
+use iced::widget::{Button, Column, Container, Row, Scrollable, Text};
+use iced::{theme, Length};
+use iced_aw::{Card, Modal};
+use internationalization::t;
+use ship_internal::application::update::update_manage::UpdateManager;
+use ship_internal::application::update::{UpdatePlan, UpdateTaskStatus};
+use std::sync::{Arc, Mutex};
+
+// files shown in a list at most, the rest are counted only
+const MAX_SHOWN_FILES: usize = 200;
+
+// shown when an update is waiting for confirmation, or a preview is finished
+pub fn make_update_plan_modal(
+    app_server_id: u64,
+    app_id: u64,
+    update_manager: Arc<Mutex<UpdateManager>>,
+) -> Option<Container<'static, Message>> {
+    let update_manager_g = update_manager.lock().unwrap();
+    let (plan, is_awaiting_confirmation) = match update_manager_g
+        .get_update_task_by_app_server_id(app_server_id)
+        .map(|x| &x.status)
+    {
+        Some(UpdateTaskStatus::AwaitingConfirmation { plan }) => (plan.clone(), true),
+        Some(UpdateTaskStatus::Planned { plan }) => (plan.clone(), false),
+        _ => return None,
+    };
+    drop(update_manager_g);
+
+    let close_message = if is_awaiting_confirmation {
+        Message::CancelUpdate {
+            app_server_id,
+            app_id,
+        }
+    } else {
+        Message::DismissUpdateResult { app_server_id }
+    };
+    let close_message_1 = close_message.clone();
+    let modal = Modal::new(true, "", move || {
+        let mut body_c = Column::new().spacing(10);
+        body_c = body_c.push(Text::new(make_summary(&plan)));
+
+        if !plan.deleted_files.is_empty() {
+            body_c = body_c
+                .push(Text::new(t!("update_plan_deleted_files")))
+                .push(make_file_list(&plan.deleted_files));
+        }
+
+        let mut btn_panel = Row::new().spacing(10);
+        if is_awaiting_confirmation {
+            btn_panel = btn_panel
+                .push(
+                    Button::new(Text::new(t!("update_plan_confirm")))
+                        .on_press(Message::ConfirmUpdate { app_server_id }),
+                )
+                .push(
+                    Button::new(Text::new(t!("cancel_update")))
+                        .style(theme::Button::Secondary)
+                        .on_press(close_message_1.clone()),
+                );
+        } else {
+            btn_panel = btn_panel.push(
+                Button::new(Text::new(t!("update_plan_close")))
+                    .style(theme::Button::Secondary)
+                    .on_press(close_message_1.clone()),
+            );
+        }
+        body_c = body_c.push(btn_panel);
+
+        Card::new(Text::new(t!("update_plan")), body_c)
+            .max_width(500.0)
+            .into()
+    })
+    .backdrop(close_message.clone())
+    .on_esc(close_message);
+
+    Some(Container::new(Column::new().push(modal)))
+}
+
+pub fn make_file_list(files: &[String]) -> Scrollable<'static, Message> {
+    let mut c = Column::new().spacing(2);
+    for x in files.iter().take(MAX_SHOWN_FILES) {
+        c = c.push(Text::new(x.clone()).size(14));
+    }
+    if files.len() > MAX_SHOWN_FILES {
+        c = c.push(Text::new(format!("... +{}", files.len() - MAX_SHOWN_FILES)).size(14));
+    }
+    Scrollable::new(c).height(150).width(Length::Fill)
+}
+
+fn make_summary(plan: &UpdatePlan) -> String {
+    let mut s = format!(
+        "{}: {}  {}: {}  {}: {}\n{}: {}",
+        t!("update_plan_added_files"),
+        plan.added_files,
+        t!("update_plan_changed_files"),
+        plan.changed_files,
+        t!("update_plan_deleted_files"),
+        plan.deleted_files.len(),
+        t!("update_plan_download_size"),
+        util::convert::file_size::simple_format(plan.download_bytes)
+    );
+    if plan.protected_files > 0 {
+        s = format!(
+            "{}\n{} {}",
+            s,
+            plan.protected_files,
+            t!("update_tip_protected_files")
+        );
+    }
+    s
+}
@@ -0,0 +1,67 @@
+use crate::gui::view::app::update_plan::make_file_list;
+use crate::gui::Message;
+use iced::theme;
+use iced::widget::{Button, Column, Container, Row, Text};
+use iced_aw::{Card, Modal};
+use internationalization::t;
+use ship_internal::application::update::update_manage::UpdateManager;
+use ship_internal::application::update::UpdateTaskStatus;
+use std::sync::{Arc, Mutex};
+
+// shown when a verification is finished
+pub fn make_verify_report_modal(
+    app_server_id: u64,
+    update_manager: Arc<Mutex<UpdateManager>>,
+) -> Option<Container<'static, Message>> {
+    let update_manager_g = update_manager.lock().unwrap();
+    let report = match update_manager_g
+        .get_update_task_by_app_server_id(app_server_id)
+        .map(|x| &x.status)
+    {
+        Some(UpdateTaskStatus::Verified { report }) => report.clone(),
+        _ => return None,
+    };
+    drop(update_manager_g);
+
+    let close_message = Message::DismissUpdateResult { app_server_id };
+    let close_message_1 = close_message.clone();
+    let modal = Modal::new(true, "", move || {
+        let mut body_c = Column::new().spacing(10);
+        let lists = [
+            (t!("verify_missing_files"), &report.missing_files),
+            (t!("verify_modified_files"), &report.modified_files),
+            (t!("verify_extra_files"), &report.extra_files),
+        ];
+        for (title, files) in lists {
+            body_c = body_c.push(Text::new(format!("{}: {}", title, files.len())));
+            if !files.is_empty() {
+                body_c = body_c.push(make_file_list(files));
+            }
+        }
+        if !report.is_repairable() {
+            body_c = body_c.push(Text::new(t!("verify_no_broken_files")));
+        }
+
+        let mut btn_panel = Row::new().spacing(10);
+        if report.is_repairable() {
+            btn_panel = btn_panel.push(
+                Button::new(Text::new(t!("verify_repair")))
+                    .on_press(Message::RepairFiles { app_server_id }),
+            );
+        }
+        btn_panel = btn_panel.push(
+            Button::new(Text::new(t!("update_plan_close")))
+                .style(theme::Button::Secondary)
+                .on_press(close_message_1.clone()),
+        );
+        body_c = body_c.push(btn_panel);
+
+        Card::new(Text::new(t!("verify_report")), body_c)
+            .max_width(500.0)
+            .into()
+    })
+    .backdrop(close_message.clone())
+    .on_esc(close_message);
+
+    Some(Container::new(Column::new().push(modal)))
+}
@@ -3,6 +3,7 @@ mod app;
 mod home;
 pub mod navbar;
 mod settings;
+mod update_history;
 mod version;
 
 use crate::gui::view::app::make_app_page;
@@ -28,6 +29,11 @@ pub fn make_view(s: &Gui) -> Container<'static, Message> {
         c = c.push(about_modal);
     }
 
+    if let Some((_, records)) = &s.update_history {
+        let update_history_modal = update_history::make_update_history_content(records.clone());
+        c = c.push(update_history_modal);
+    }
+
     let version_modal = version::make_version_update_content(Arc::clone(&s.version_manager));
     c = c.push(version_modal);
 
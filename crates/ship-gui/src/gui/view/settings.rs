@@ -1,12 +1,29 @@
 use crate::gui::view::DEFAULT_PADDING;
 use crate::gui::Message;
-use iced::widget::{Button, Column, Container, Row, Text, TextInput};
+use iced::widget::{Button, Checkbox, Column, Container, PickList, Row, Text, TextInput};
 use iced::Alignment;
 use iced_aw::Card;
 use internationalization::t;
 use ship_internal::application::settings::SettingsManager;
+use std::fmt;
 use std::sync::{Arc, Mutex};
 
+// KiB/s
+const DOWNLOAD_LIMIT_OPTIONS: [u64; 8] = [0, 512, 1024, 2048, 5120, 10240, 20480, 51200];
+
+// download limit in KiB/s, 0 means unlimited
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DownloadLimitOption(pub u64);
+
+impl fmt::Display for DownloadLimitOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => write!(f, "{}", t!("download_limit_unlimited")),
+            x => write!(f, "{}/s", util::convert::file_size::simple_format(x * 1024)),
+        }
+    }
+}
+
 pub fn make_settings_page(
     settings_manager: Arc<Mutex<SettingsManager>>,
 ) -> Container<'static, Message> {
@@ -23,6 +40,8 @@ pub fn make_settings_page(
             .data_dir_path
             .to_string(),
     ));
+    let is_deep_verify = settings_manager_g.settings.update_settings.is_deep_verify;
+    let download_limit = settings_manager_g.settings.update_settings.download_limit;
     drop(settings_manager_g);
     let data_dir_c = Row::new()
         .align_items(Alignment::Center)
@@ -34,10 +53,45 @@ pub fn make_settings_page(
     let card = Card::new(Text::new(""), data_dir_c);
     let mut c = Column::new();
     c = c.push(card);
+    c = c.push(make_update_settings(is_deep_verify, download_limit));
     c = c.push(make_cache_settings());
 
     Container::new(c).padding(DEFAULT_PADDING)
 }
+pub fn make_update_settings(
+    is_deep_verify: bool,
+    download_limit: u64,
+) -> Container<'static, Message> {
+    let mut c = Column::new();
+
+    let checkbox = Checkbox::new(t!("deep_verify"), is_deep_verify, Message::ToggleDeepVerify);
+    let card = Card::new(Text::new(""), Row::new().push(checkbox));
+    c = c.push(card);
+
+    // the configured limit may not be one of the options
+    let mut options: Vec<DownloadLimitOption> = DOWNLOAD_LIMIT_OPTIONS
+        .iter()
+        .map(|x| DownloadLimitOption(*x))
+        .collect();
+    if !DOWNLOAD_LIMIT_OPTIONS.contains(&download_limit) {
+        options.push(DownloadLimitOption(download_limit));
+        options.sort_by_key(|x| x.0);
+    }
+    let download_limit_list =
+        PickList::new(options, Some(DownloadLimitOption(download_limit)), |x| {
+            Message::SetDownloadLimit(x.0)
+        });
+    let download_limit_c = Row::new()
+        .align_items(Alignment::Center)
+        .spacing(5)
+        .push(Text::new(t!("download_limit")))
+        .push(download_limit_list);
+    let card = Card::new(Text::new(""), download_limit_c);
+    c = c.push(card);
+
+    Container::new(c).padding(DEFAULT_PADDING)
+}
+
 pub fn make_cache_settings() -> Container<'static, Message> {
     let mut c = Column::new();
 
@@ -0,0 +1,81 @@
+use crate::gui::Message;
+use iced::widget::{Button, Column, Container, Scrollable, Text};
+use iced::{theme, Length};
+use iced_aw::{Card, Modal};
+use internationalization::t;
+use ship_internal::application::update::history::{UpdateRecord, UpdateResult};
+use ship_internal::application::update::UpdateMode;
+
+// records are newest first
+pub fn make_update_history_content(records: Vec<UpdateRecord>) -> Container<'static, Message> {
+    let modal = Modal::new(true, "", move || {
+        let mut list_c = Column::new().spacing(10);
+        if records.is_empty() {
+            list_c = list_c.push(Text::new(t!("update_history_empty")));
+        }
+        for x in &records {
+            list_c = list_c.push(Text::new(make_record_text(x)).size(14));
+        }
+
+        let body_c = Column::new()
+            .spacing(10)
+            .push(Scrollable::new(list_c).height(300).width(Length::Fill))
+            .push(
+                Button::new(Text::new(t!("update_plan_close")))
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::CloseUpdateHistory),
+            );
+
+        Card::new(Text::new(t!("update_history")), body_c)
+            .max_width(500.0)
+            .into()
+    })
+    .backdrop(Message::CloseUpdateHistory)
+    .on_esc(Message::CloseUpdateHistory);
+    let mut c = Column::new();
+    c = c.push(modal);
+    Container::new(c)
+}
+
+fn make_record_text(record: &UpdateRecord) -> String {
+    let mode = match record.mode {
+        UpdateMode::Update => t!("update_history_update"),
+        UpdateMode::Preview => t!("update_preview"),
+        UpdateMode::Verify => t!("verify_files"),
+        UpdateMode::Repair => t!("update_history_repair"),
+        UpdateMode::Resume => t!("update_history_resume"),
+    };
+    let result = match record.result {
+        UpdateResult::Finished => t!("update_history_finished"),
+        UpdateResult::Canceled => t!("update_history_canceled"),
+        UpdateResult::Paused => t!("update_history_paused"),
+        UpdateResult::Failed => t!("update_history_failed"),
+    };
+    let mut s = format!(
+        "{} - {}  {}  {}\n{}: {}  {}: {}  {}: {}  {}: {}",
+        util::time::format_timestamp_to_datetime(record.start_time),
+        util::time::format_timestamp_to_datetime(record.end_time),
+        mode,
+        result,
+        t!("update_plan_added_files"),
+        record.added_files,
+        t!("update_plan_changed_files"),
+        record.changed_files,
+        t!("update_plan_deleted_files"),
+        record.deleted_files,
+        t!("update_history_transferred"),
+        util::convert::file_size::simple_format(record.transferred_bytes)
+    );
+    if record.protected_files > 0 {
+        s = format!(
+            "{}\n{} {}",
+            s,
+            record.protected_files,
+            t!("update_tip_protected_files")
+        );
+    }
+    if let Some(error) = &record.error {
+        s = format!("{}\n{}: {}", s, t!("update_history_error"), error);
+    }
+    s
+}
@@ -2,6 +2,7 @@ use crate::application::app::app_server::{AppServer, AppServerInfo, AppServers};
 use crate::application::app::{App, AppManager, Apps};
 use crate::request;
 use crate::request::app_server::announcement::AnnouncementVo;
+use crate::request::DOWNLOAD_RATE_LIMITER;
 use crate::types::banner::Banner;
 use image::ImageFormat;
 use log::warn;
@@ -12,6 +13,7 @@ use std::time::Duration;
 use std::{fs, io, thread};
 use util::filepath;
 use util::hash::md5;
+use util::rate_limit::RateLimitedReader;
 
 pub enum Error {
     GetAppsFailed,
@@ -51,6 +53,7 @@ pub fn start(app_manager: Arc<Mutex<AppManager>>) -> Result<(), Error> {
             dir_name: app_tmp.dir_name.clone(),
             priority: app_tmp.priority,
             launch: app_tmp.launch.clone(),
+            keep_rules: app_tmp.keep_rules.clone(),
             app_server_info: AppServerInfo::new(app_servers),
             selected_app_server_id,
         };
@@ -269,12 +272,13 @@ fn get_local_image_path(
 
     let tmp_file_name = format!("tmp_{}", chrono::Utc::now().timestamp());
     let tmp_file_path = banner_base_path.join(tmp_file_name);
-    let mut resp = reqwest::blocking::get(url).map_err(|_| DownloadImageError::DownloadFailed)?;
+    let resp = reqwest::blocking::get(url).map_err(|_| DownloadImageError::DownloadFailed)?;
     let f = fs::File::create(&tmp_file_path).map_err(|_| DownloadImageError::CreateFileFailed)?;
     let mut writer = io::BufWriter::new(f);
+    let mut reader = RateLimitedReader::new(resp, &DOWNLOAD_RATE_LIMITER);
     let mut buf = [0; 1024 * 1024];
     loop {
-        let n = resp
+        let n = reader
             .read(&mut buf)
             .map_err(|_| DownloadImageError::ReadDownloadContentFailed)?;
         if n == 0 {
@@ -29,6 +29,8 @@ pub struct AppServer {
     pub banners: Vec<Banner>,
     pub priority: i64,
     pub launch_status: LaunchStatus,
+    // the server scanned new files since the last finished update
+    pub update_available: bool,
 }
 
 impl AppServer {
@@ -50,6 +52,7 @@ impl AppServer {
             banners: Default::default(),
             priority,
             launch_status: Default::default(),
+            update_available: false,
         }
     }
 
@@ -1,5 +1,5 @@
 use crate::application::app::app_server::AppServerInfo;
-use crate::types::common::Launch;
+use crate::types::common::{KeepRule, Launch};
 use std::collections::HashMap;
 
 pub mod app_manage;
@@ -40,6 +40,7 @@ pub struct App {
     pub dir_name: String,
     pub priority: i64,
     pub launch: Launch,
+    pub keep_rules: Vec<KeepRule>,
     pub app_server_info: AppServerInfo,
     pub selected_app_server_id: Option<u64>,
 }
@@ -76,6 +76,8 @@ impl App {
             }
         }
 
+        settings_manager.settings.update_settings.download_limit = CONFIG.update.download_limit;
+
         drop(settings_manager);
 
         let mut version_manager_g = self.version_manager.lock().unwrap();
@@ -92,5 +94,10 @@ impl App {
         );
 
         app_manage::start(Arc::clone(&self.app_manager));
+        update::restore_paused_updates(
+            Arc::clone(&self.update_manager),
+            Arc::clone(&self.app_manager),
+            Arc::clone(&self.settings_manager),
+        );
     }
 }
@@ -0,0 +1,138 @@
+use crate::application::update::diff::{self, CasePolicy};
+use crate::cache;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use util::hash::HashAlgorithm;
+
+// hash of files from the last scan or sync of a data path, a file is not hashed again if its
+// size and mtime are not changed
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ScanIndex {
+    pub hash_algorithm: String,
+    pub files: HashMap<String, IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IndexEntry {
+    pub size: u64,
+    // nanoseconds since unix epoch
+    pub mtime: u64,
+    pub hash: String,
+}
+
+impl ScanIndex {
+    pub fn new(hash_algorithm: HashAlgorithm) -> Self {
+        Self {
+            hash_algorithm: hash_algorithm.to_string(),
+            files: Default::default(),
+        }
+    }
+
+    pub fn get_hash(&self, relative_path: &str, size: u64, mtime: u64) -> Option<&str> {
+        let entry = self.files.get(&get_key(relative_path))?;
+        if entry.size != size || entry.mtime != mtime {
+            return None;
+        }
+        Some(&entry.hash)
+    }
+
+    pub fn insert(&mut self, relative_path: &str, size: u64, mtime: u64, hash: &str) {
+        self.files.insert(
+            get_key(relative_path),
+            IndexEntry {
+                size,
+                mtime,
+                hash: hash.to_string(),
+            },
+        );
+    }
+
+    pub fn remove(&mut self, relative_path: &str) {
+        self.files.remove(&get_key(relative_path));
+    }
+}
+
+// scanned paths have native separators, synced paths those of the server, both are looked up
+// normalized like the diff does
+fn get_key(relative_path: &str) -> String {
+    diff::normalize_path(relative_path, CasePolicy::native())
+}
+
+pub fn get_mtime(metadata: &fs::Metadata) -> Option<u64> {
+    let d = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(d.as_nanos()).ok()
+}
+
+// an empty index is returned if not found, or it is made by another hash algorithm
+pub fn load(data_path: &str, hash_algorithm: HashAlgorithm) -> ScanIndex {
+    let r = get_index_file_path(data_path).and_then(|p| {
+        if !Path::new(&p).exists() {
+            return None;
+        }
+        let d = fs::read_to_string(&p).ok()?;
+        serde_json::from_str::<ScanIndex>(&d)
+            .map_err(|e| {
+                warn!("deserialize scan index failed, path: {}, err: {}", p, e);
+            })
+            .ok()
+    });
+    match r {
+        Some(index) if index.hash_algorithm == hash_algorithm.to_string() => {
+            debug!(
+                "scan index loaded, data_path: {}, files: {}",
+                data_path,
+                index.files.len()
+            );
+            index
+        }
+        _ => ScanIndex::new(hash_algorithm),
+    }
+}
+
+pub fn save(data_path: &str, index: &ScanIndex) {
+    let p = match get_index_file_path(data_path) {
+        Some(p) => p,
+        None => {
+            warn!("get scan index path failed, data_path: {}", data_path);
+            return;
+        }
+    };
+    if let Some(parent) = Path::new(&p).parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let r = serde_json::to_string(index)
+        .map_err(|e| e.to_string())
+        .and_then(|j| fs::write(&p, j).map_err(|e| e.to_string()));
+    if let Err(e) = r {
+        warn!("save scan index failed, path: {}, err: {}", p, e);
+    }
+}
+
+fn get_index_file_path(data_path: &str) -> Option<String> {
+    let cache_dir_path = cache::get_cache_dir_path().ok()?;
+    let p = Path::new(&cache_dir_path)
+        .join("scan-index")
+        .join(util::hash::md5::md5_string(data_path));
+    Some(p.to_str()?.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::scan::index::ScanIndex;
+    use util::hash::HashAlgorithm;
+
+    #[test]
+    fn test_scan_index_path_separator() {
+        let mut index = ScanIndex::new(HashAlgorithm::Md5);
+        // inserted by a sync, looked up by a scan on windows
+        index.insert("data/a.pak", 1, 2, "a");
+        assert_eq!(index.get_hash("data\\a.pak", 1, 2), Some("a"));
+        assert_eq!(index.get_hash("./data/a.pak", 1, 3), None);
+        index.remove("data\\a.pak");
+        assert!(index.files.is_empty());
+    }
+}
@@ -1,9 +1,16 @@
+pub mod index;
+
+use crate::application::scan::index::ScanIndex;
+use crate::application::update::metadata;
+use crate::config::CONFIG;
 use crate::types::common::{ClientFileInfo, FileInfo, FileType, ScanStatus};
 use chrono::Local;
 use log::{debug, warn};
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use util::hash::HashAlgorithm;
 
 #[derive(Debug)]
 pub enum Error {
@@ -14,12 +21,80 @@ pub enum Error {
     Cancel,
 }
 
-pub fn scan(base_path: &str, is_cancel: Arc<AtomicBool>) -> Result<ClientFileInfo, Error> {
+// updated while scanning, shared with whoever shows the progress
+#[derive(Default, Debug)]
+pub struct ScanCounter {
+    scanned_files: AtomicU64,
+    hashed_bytes: AtomicU64,
+    // bytes of the files to hash, known after all files are walked
+    total_bytes: AtomicU64,
+}
+
+impl ScanCounter {
+    pub fn get_progress(&self) -> ScanProgress {
+        ScanProgress {
+            scanned_files: self.scanned_files.load(Ordering::Relaxed),
+            hashed_bytes: self.hashed_bytes.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct ScanProgress {
+    pub scanned_files: u64,
+    pub hashed_bytes: u64,
+    pub total_bytes: u64,
+}
+
+// a file found by walking, hash is calculated later by the workers
+struct HashJob {
+    index: usize,
+    absolute_path: String,
+    mtime: u64,
+}
+
+// only files whose size or mtime changed since the last scan are hashed, all files are hashed
+// when is_deep_verify, the index is saved for the next scan
+pub fn scan_incremental(
+    base_path: &str,
+    hash_algorithm: HashAlgorithm,
+    is_deep_verify: bool,
+    counter: &ScanCounter,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<ClientFileInfo, Error> {
+    let old_index = if is_deep_verify {
+        None
+    } else {
+        Some(index::load(base_path, hash_algorithm))
+    };
+    let mut new_index = ScanIndex::new(hash_algorithm);
+    let cfi = do_scan(
+        base_path,
+        hash_algorithm,
+        old_index.as_ref(),
+        &mut new_index,
+        counter,
+        is_cancel,
+    )?;
+    index::save(base_path, &new_index);
+    Ok(cfi)
+}
+
+fn do_scan(
+    base_path: &str,
+    hash_algorithm: HashAlgorithm,
+    old_index: Option<&ScanIndex>,
+    new_index: &mut ScanIndex,
+    counter: &ScanCounter,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<ClientFileInfo, Error> {
     let p = Path::new(base_path);
     if !p.exists() {
         return Err(Error::PathNotExitError);
     }
     let mut files: Vec<FileInfo> = vec![];
+    let mut hash_jobs: Vec<HashJob> = vec![];
 
     debug!("{}", "scan start");
 
@@ -51,6 +126,7 @@ pub fn scan(base_path: &str, is_cancel: Arc<AtomicBool>) -> Result<ClientFileInf
                 let file_type;
                 let mut size = 0;
                 let mut hash_sum = "".to_string();
+                let mut mode = None;
                 if entry.path().is_symlink() {
                     file_type = FileType::Symlink;
                     size = entry.metadata().unwrap().len();
@@ -58,28 +134,36 @@ pub fn scan(base_path: &str, is_cancel: Arc<AtomicBool>) -> Result<ClientFileInf
                     file_type = FileType::Dir;
                 } else if entry.path().is_file() {
                     file_type = FileType::File;
-                    size = entry.metadata().unwrap().len();
-                    let hash_sum_r = util::hash::md5::md5_file(absolute_path);
-                    match hash_sum_r {
-                        Ok(h) => {
-                            hash_sum = h;
-                        }
-                        Err(e) => {
-                            warn!("calc hash failed, err: {}, rel_path: {}", e, relative_path);
-                            return Err(Error::CalcHashError);
-                        }
+                    let metadata = entry.metadata().map_err(|_| Error::ScanError)?;
+                    size = metadata.len();
+                    mode = metadata::get_mode(&metadata);
+                    let mtime = index::get_mtime(&metadata).unwrap_or_default();
+                    let indexed_hash_sum_o =
+                        old_index.and_then(|x| x.get_hash(relative_path, size, mtime));
+                    if let Some(h) = indexed_hash_sum_o {
+                        hash_sum = h.to_string();
+                        new_index.insert(relative_path, size, mtime, &hash_sum);
+                    } else {
+                        hash_jobs.push(HashJob {
+                            index: files.len(),
+                            absolute_path: absolute_path.to_string(),
+                            mtime,
+                        });
                     }
                 } else {
                     warn!("ignored file type, relative_path: {}", relative_path);
                     continue;
                 }
 
-                let file = FileInfo::new(&relative_path, file_type, size, &hash_sum);
+                let mut file =
+                    FileInfo::new(&relative_path, file_type, size, &hash_sum, hash_algorithm);
+                file.mode = mode;
                 debug!(
                     "abs_path: {}, rel_path: {}, file: {:?}",
                     absolute_path, relative_path, file
                 );
                 files.push(file);
+                counter.scanned_files.fetch_add(1, Ordering::Relaxed);
             }
             Err(_) => {
                 return Err(Error::ScanError);
@@ -87,6 +171,15 @@ pub fn scan(base_path: &str, is_cancel: Arc<AtomicBool>) -> Result<ClientFileInf
         }
     }
 
+    let total_bytes = hash_jobs.iter().map(|x| files[x.index].size).sum();
+    counter.total_bytes.store(total_bytes, Ordering::Relaxed);
+    let hash_sums = hash_files(&hash_jobs, hash_algorithm, counter, is_cancel)?;
+    for (job, hash_sum) in hash_jobs.iter().zip(hash_sums) {
+        let file = &mut files[job.index];
+        new_index.insert(&file.relative_path, file.size, job.mtime, &hash_sum);
+        file.hash = hash_sum;
+    }
+
     let cfi: ClientFileInfo =
         ClientFileInfo::new(ScanStatus::Completed, Local::now().timestamp(), files);
 
@@ -95,3 +188,67 @@ pub fn scan(base_path: &str, is_cancel: Arc<AtomicBool>) -> Result<ClientFileInf
 
     Ok(cfi)
 }
+
+// hash files by a pool of workers, hash sums are returned in the order of jobs
+fn hash_files(
+    hash_jobs: &[HashJob],
+    hash_algorithm: HashAlgorithm,
+    counter: &ScanCounter,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<Vec<String>, Error> {
+    let worker_count = get_worker_count().clamp(1, hash_jobs.len().max(1));
+    let next = AtomicUsize::new(0);
+    let is_failed = AtomicBool::new(false);
+    let hash_sums: Mutex<Vec<String>> = Mutex::new(vec!["".to_string(); hash_jobs.len()]);
+
+    thread::scope(|s| {
+        for _ in 0..worker_count {
+            s.spawn(|| loop {
+                if is_cancel.load(Ordering::Relaxed) || is_failed.load(Ordering::Relaxed) {
+                    return;
+                }
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let job = match hash_jobs.get(i) {
+                    None => return,
+                    Some(x) => x,
+                };
+                let on_progress = |n| {
+                    counter.hashed_bytes.fetch_add(n, Ordering::Relaxed);
+                };
+                match hash_algorithm.hash_file_cancelable(
+                    &job.absolute_path,
+                    &is_cancel,
+                    Some(&on_progress),
+                ) {
+                    Ok(h) => {
+                        hash_sums.lock().unwrap()[i] = h;
+                    }
+                    Err(e) => {
+                        if !is_cancel.load(Ordering::Relaxed) {
+                            warn!("calc hash failed, err: {}, path: {}", e, job.absolute_path);
+                            is_failed.store(true, Ordering::Relaxed);
+                        }
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    if is_cancel.load(Ordering::Relaxed) {
+        return Err(Error::Cancel);
+    }
+    if is_failed.load(Ordering::Relaxed) {
+        return Err(Error::CalcHashError);
+    }
+    Ok(hash_sums.into_inner().unwrap())
+}
+
+fn get_worker_count() -> usize {
+    match CONFIG.update.scan_workers {
+        0 => thread::available_parallelism()
+            .map(|x| x.get())
+            .unwrap_or(1),
+        x => x,
+    }
+}
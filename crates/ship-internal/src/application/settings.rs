@@ -6,6 +6,7 @@ pub struct SettingsManager {
 #[derive(Default)]
 pub struct Settings {
     pub general_settings: GeneralSettings,
+    pub update_settings: UpdateSettings,
 }
 
 pub struct GeneralSettings {
@@ -21,3 +22,11 @@ impl Default for GeneralSettings {
         }
     }
 }
+
+#[derive(Default)]
+pub struct UpdateSettings {
+    // hash all local files, instead of trusting the scan index
+    pub is_deep_verify: bool,
+    // KiB/s, 0 means unlimited
+    pub download_limit: u64,
+}
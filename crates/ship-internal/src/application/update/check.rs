@@ -0,0 +1,186 @@
+use crate::application::app::AppManager;
+use crate::application::update::history;
+use crate::application::update::history::UpdateRecord;
+use crate::application::update::update_manage::UpdateManager;
+use crate::application::update::{TaskPriority, UpdateTaskStatus};
+use crate::config::CONFIG;
+use crate::request;
+use crate::types::common::ScanStatus;
+use log::{debug, info, warn};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// the app servers are loaded in the meantime
+const FIRST_CHECK_DELAY: Duration = Duration::from_secs(10);
+
+// checks installed app servers for a new scan of their files on the server, app servers never
+// updated successfully are not checked, there is nothing to compare with
+pub fn start(update_manager: Arc<Mutex<UpdateManager>>, app_manager: Arc<Mutex<AppManager>>) {
+    let check_interval = CONFIG.update.check_interval;
+    if check_interval == 0 {
+        return;
+    }
+    thread::spawn(move || {
+        thread::sleep(FIRST_CHECK_DELAY);
+        loop {
+            check_all(Arc::clone(&update_manager), Arc::clone(&app_manager));
+            thread::sleep(Duration::from_secs(check_interval));
+        }
+    });
+}
+
+fn check_all(update_manager: Arc<Mutex<UpdateManager>>, app_manager: Arc<Mutex<AppManager>>) {
+    let mut app_servers = vec![];
+    let app_manager_g = app_manager.lock().unwrap();
+    for app in app_manager_g.apps.values() {
+        for app_server in app.app_server_info.servers.values() {
+            app_servers.push((
+                app_server.id,
+                app.code.clone(),
+                app_server.address.to_address_string(),
+            ));
+        }
+    }
+    drop(app_manager_g);
+
+    for (app_server_id, app_code, address) in app_servers {
+        let update_manager_g = update_manager.lock().unwrap();
+        let is_processing = update_manager_g
+            .is_update_processing(app_server_id)
+            .unwrap_or(false);
+        // a new update would replace the paused one
+        let is_paused = update_manager_g
+            .get_update_task_by_app_server_id(app_server_id)
+            .is_some_and(|x| matches!(x.status, UpdateTaskStatus::Paused));
+        drop(update_manager_g);
+        if is_processing || is_paused {
+            continue;
+        }
+        let last_record = match history::get_last_finished(app_server_id) {
+            None => continue,
+            Some(x) => x,
+        };
+        let is_available = match is_update_available(&address, &last_record) {
+            None => continue,
+            Some(x) => x,
+        };
+        debug!(
+            "update checked, app_server_id: {}, update_available: {}",
+            app_server_id, is_available
+        );
+        set_update_available(app_server_id, is_available, Arc::clone(&app_manager));
+
+        let is_auto_update = CONFIG
+            .get_app_config(&app_code)
+            .map(|x| x.auto_update)
+            .unwrap_or(false);
+        if is_available && is_auto_update {
+            info!("start auto update, app_server_id: {}", app_server_id);
+            let update_manager_g = update_manager.lock().unwrap();
+            let _ = update_manager_g.start_task(app_server_id, TaskPriority::Background);
+            drop(update_manager_g);
+        }
+    }
+}
+
+// None if the server can not tell, it is not reachable or still scanning
+fn is_update_available(address: &str, last_record: &UpdateRecord) -> Option<bool> {
+    let (scan_status, last_scan_finish_time) = get_server_scan(address, last_record.app_server_id)?;
+    is_scan_newer(&scan_status, last_scan_finish_time, last_record)
+}
+
+// the scan is read from the app server info, the file list is megabytes for a big app and is
+// only fetched from older servers not sending the scan in the info
+fn get_server_scan(address: &str, app_server_id: u64) -> Option<(ScanStatus, i64)> {
+    let info = request::app_server::app_server_info::get_app_server_info(address)
+        .map_err(|e| {
+            warn!(
+                "check update failed, app_server_id: {}, err: {:?}",
+                app_server_id, e
+            );
+        })
+        .ok()?;
+    if let (Some(scan_status), Some(last_scan_finish_time)) =
+        (info.scan_status, info.last_scan_finish_time)
+    {
+        return Some((scan_status, last_scan_finish_time));
+    }
+    debug!(
+        "scan not in app server info, get file info, app_server_id: {}",
+        app_server_id
+    );
+    let sfi = request::app_server::file_info::get_file_info(address)
+        .map_err(|e| {
+            warn!(
+                "check update failed, app_server_id: {}, err: {:?}",
+                app_server_id, e
+            );
+        })
+        .ok()?
+        .data;
+    Some((sfi.scan_status, sfi.last_scan_finish_time))
+}
+
+fn is_scan_newer(
+    scan_status: &ScanStatus,
+    last_scan_finish_time: i64,
+    last_record: &UpdateRecord,
+) -> Option<bool> {
+    if !matches!(scan_status, ScanStatus::Completed) {
+        return None;
+    }
+    if last_record.server_scan_time != 0 {
+        return Some(last_scan_finish_time != last_record.server_scan_time);
+    }
+    // records written before the scan time was kept, by the time of the client
+    Some(last_scan_finish_time > last_record.end_time)
+}
+
+pub fn set_update_available(
+    app_server_id: u64,
+    update_available: bool,
+    app_manager: Arc<Mutex<AppManager>>,
+) {
+    let mut app_manager_g = app_manager.lock().unwrap();
+    for app in app_manager_g.apps.values_mut() {
+        if let Some(app_server) = app.app_server_info.servers.get_mut(&app_server_id) {
+            app_server.update_available = update_available;
+        }
+    }
+    drop(app_manager_g);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::check::is_scan_newer;
+    use crate::application::update::history::UpdateRecord;
+    use crate::application::update::UpdateMode;
+    use crate::types::common::ScanStatus;
+
+    #[test]
+    fn test_is_scan_newer() {
+        let mut record = UpdateRecord::new(1, UpdateMode::Update);
+        record.end_time = 200;
+        assert_eq!(is_scan_newer(&ScanStatus::Scanning, 300, &record), None);
+        // old record without the scan time, by the time of the client
+        assert_eq!(
+            is_scan_newer(&ScanStatus::Completed, 100, &record),
+            Some(false)
+        );
+        assert_eq!(
+            is_scan_newer(&ScanStatus::Completed, 300, &record),
+            Some(true)
+        );
+
+        record.server_scan_time = 100;
+        assert_eq!(
+            is_scan_newer(&ScanStatus::Completed, 100, &record),
+            Some(false)
+        );
+        assert_eq!(
+            is_scan_newer(&ScanStatus::Completed, 50, &record),
+            Some(true)
+        );
+    }
+}
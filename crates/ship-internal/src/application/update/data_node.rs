@@ -0,0 +1,175 @@
+use crate::types::common::DataNode;
+use log::warn;
+use rand::{thread_rng, Rng};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// a node is demoted for the rest of the UpdateTask after this many failures in a row
+const DEMOTE_FAILURE_COUNT: u64 = 3;
+// used as latency before the first response of a node is seen
+const DEFAULT_LATENCY_MS: u64 = 200;
+
+#[derive(Clone, Debug)]
+pub struct DataNodeStat {
+    pub name: String,
+    pub success_count: u64,
+    pub failure_count: u64,
+    pub consecutive_failure_count: u64,
+    pub latency_ms: Option<u64>,
+    pub is_demoted: bool,
+}
+
+impl DataNodeStat {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            success_count: 0,
+            failure_count: 0,
+            consecutive_failure_count: 0,
+            latency_ms: None,
+            is_demoted: false,
+        }
+    }
+
+    fn weight(&self) -> f64 {
+        let latency_ms = self.latency_ms.unwrap_or(DEFAULT_LATENCY_MS);
+        1000.0 / (latency_ms as f64 + 10.0) / (self.consecutive_failure_count as f64 + 1.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct DataNodeSelector {
+    data_nodes: Vec<DataNode>,
+    stats: Mutex<Vec<DataNodeStat>>,
+}
+
+impl DataNodeSelector {
+    pub fn new(data_nodes: Vec<DataNode>) -> Self {
+        let stats = data_nodes
+            .iter()
+            .map(|x| DataNodeStat::new(&x.name))
+            .collect();
+        Self {
+            data_nodes,
+            stats: Mutex::new(stats),
+        }
+    }
+
+    // weighted random selection among healthy nodes, demoted nodes are used only when no
+    // healthy node is left
+    pub fn select(&self, excluded: &[usize]) -> Option<(usize, DataNode)> {
+        let stats = self.stats.lock().unwrap();
+        let mut candidates: Vec<(usize, f64)> = stats
+            .iter()
+            .enumerate()
+            .filter(|(i, x)| !x.is_demoted && !excluded.contains(i))
+            .map(|(i, x)| (i, x.weight()))
+            .collect();
+        if candidates.is_empty() {
+            candidates = stats
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !excluded.contains(i))
+                .map(|(i, x)| (i, x.weight()))
+                .collect();
+        }
+        drop(stats);
+
+        let total: f64 = candidates.iter().map(|(_, w)| w).sum();
+        if candidates.is_empty() || total <= 0.0 {
+            return None;
+        }
+        let mut r = thread_rng().gen_range(0.0..total);
+        let mut index = candidates[candidates.len() - 1].0;
+        for (i, w) in candidates {
+            if r < w {
+                index = i;
+                break;
+            }
+            r -= w;
+        }
+        self.data_nodes.get(index).map(|x| (index, x.clone()))
+    }
+
+    pub fn report_latency(&self, index: usize, latency: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        if let Some(stat) = stats.get_mut(index) {
+            let ms = latency.as_millis() as u64;
+            stat.latency_ms = Some(match stat.latency_ms {
+                None => ms,
+                Some(x) => (x * 7 + ms * 3) / 10,
+            });
+        }
+    }
+
+    pub fn report_success(&self, index: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        if let Some(stat) = stats.get_mut(index) {
+            stat.success_count += 1;
+            stat.consecutive_failure_count = 0;
+        }
+    }
+
+    pub fn report_failure(&self, index: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        if let Some(stat) = stats.get_mut(index) {
+            stat.failure_count += 1;
+            stat.consecutive_failure_count += 1;
+            if !stat.is_demoted && stat.consecutive_failure_count >= DEMOTE_FAILURE_COUNT {
+                warn!("data node demoted, stat: {:?}", stat);
+                stat.is_demoted = true;
+            }
+        }
+    }
+
+    pub fn get_stats(&self) -> Vec<DataNodeStat> {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::data_node::DataNodeSelector;
+    use crate::types::common::{Address, DataNode};
+
+    fn make_selector(count: u16) -> DataNodeSelector {
+        let data_nodes = (0..count)
+            .map(|i| DataNode {
+                name: format!("node-{}", i),
+                address: Address::new("http", "127.0.0.1", 8000 + i),
+            })
+            .collect();
+        DataNodeSelector::new(data_nodes)
+    }
+
+    #[test]
+    fn test_select_demoted_only_as_last_resort() {
+        let selector = make_selector(2);
+        for _ in 0..3 {
+            selector.report_failure(0);
+        }
+        assert!(selector.get_stats()[0].is_demoted);
+        assert!(!selector.get_stats()[1].is_demoted);
+
+        // the healthy node is always chosen while it is not excluded
+        for _ in 0..100 {
+            assert_eq!(selector.select(&[]).unwrap().0, 1);
+        }
+        // failover to the demoted node when the healthy one failed for this request
+        assert_eq!(selector.select(&[1]).unwrap().0, 0);
+        assert!(selector.select(&[0, 1]).is_none());
+    }
+
+    #[test]
+    fn test_report_success_resets_failures() {
+        let selector = make_selector(1);
+        selector.report_failure(0);
+        selector.report_failure(0);
+        selector.report_success(0);
+        selector.report_failure(0);
+        let stat = &selector.get_stats()[0];
+        assert_eq!(stat.failure_count, 3);
+        assert_eq!(stat.consecutive_failure_count, 1);
+        assert!(!stat.is_demoted);
+    }
+}
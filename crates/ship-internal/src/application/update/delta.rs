@@ -0,0 +1,434 @@
+use crate::application::update::data_node::DataNodeSelector;
+use crate::application::update::sync::{
+    get_content_range_start, get_download_url, with_data_node, SyncError, SyncTask, TaskProgress,
+};
+use crate::request;
+use crate::request::data_node::block_checksum::BlockChecksumsVo;
+use crate::request::DOWNLOAD_RATE_LIMITER;
+use log::{debug, info, warn};
+use reqwest::{header, StatusCode};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use util::hash::rolling::RollingChecksum;
+use util::hash::HashAlgorithm;
+use util::rate_limit::RateLimitedReader;
+
+// small files are cheaper to download as a whole
+pub const DELTA_SYNC_MIN_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+const READ_SIZE: usize = 1024 * 1024;
+
+// a block is held in memory while it is copied, a larger size from the server is not trusted
+const MAX_BLOCK_SIZE: u64 = 8 * 1024 * 1024;
+
+// rebuild the file of the task into part_file_path, blocks found in local_file_path are reused,
+// other blocks are fetched from data nodes by Range requests
+pub fn delta_sync(
+    task: &SyncTask,
+    data_node_selector: &DataNodeSelector,
+    local_file_path: &PathBuf,
+    part_file_path: &PathBuf,
+    progress: &TaskProgress,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<(), SyncError> {
+    let checksums = with_data_node(data_node_selector, |index, data_node| {
+        let start_time = Instant::now();
+        let r = request::data_node::block_checksum::get_block_checksums(
+            &data_node.address.to_address_string(),
+            &task.file_info.relative_path,
+        );
+        data_node_selector.report_latency(index, start_time.elapsed());
+        r.map_err(|e| {
+            debug!("get block checksums failed, err: {:?}", e);
+            SyncError::DeltaNotAvailable
+        })
+    })?;
+    check_block_checksums(task, &checksums)?;
+
+    let file_size = task.file_info.size;
+    let local_offsets = find_local_blocks(
+        local_file_path,
+        file_size,
+        &checksums,
+        task.file_info.hash_algorithm,
+        Arc::clone(&is_cancel),
+    )?;
+    info!(
+        "delta sync, reused blocks: {}/{}, file_info: {:?}",
+        local_offsets.iter().filter(|x| x.is_some()).count(),
+        local_offsets.len(),
+        task.file_info
+    );
+
+    let block_size = checksums.block_size;
+    let mut local_file =
+        fs::File::open(local_file_path).map_err(|_| SyncError::ReadLocalFileFailed)?;
+    let f = fs::File::create(part_file_path).map_err(|_| SyncError::CreateFileFailed)?;
+    let mut writer = ProgressWriter {
+        inner: io::BufWriter::with_capacity(READ_SIZE, f),
+        progress,
+    };
+    let mut buf = vec![0; block_size as usize];
+    let mut i = 0;
+    while i < local_offsets.len() {
+        if is_cancel.load(Ordering::Relaxed) {
+            // part file is a valid prefix of the new file, it can be resumed next time
+            writer.flush().map_err(|_| SyncError::CreateFileFailed)?;
+            return Err(SyncError::Cancel);
+        }
+
+        let start = i as u64 * block_size;
+        match local_offsets[i] {
+            Some(local_offset) => {
+                let len = block_size.min(file_size - start) as usize;
+                local_file
+                    .seek(SeekFrom::Start(local_offset))
+                    .and_then(|_| local_file.read_exact(&mut buf[..len]))
+                    .map_err(|_| SyncError::ReadLocalFileFailed)?;
+                writer
+                    .write_all(&buf[..len])
+                    .map_err(|_| SyncError::WriteDownloadContentFailed)?;
+                i += 1;
+            }
+            None => {
+                // fetch continuous missing blocks by one request
+                let mut j = i;
+                while j < local_offsets.len() && local_offsets[j].is_none() {
+                    j += 1;
+                }
+                let end = (j as u64 * block_size).min(file_size);
+                let mut written = 0;
+                with_data_node(data_node_selector, |index, data_node| {
+                    download_range(
+                        data_node_selector,
+                        index,
+                        &get_download_url(data_node, task),
+                        start..end,
+                        &mut written,
+                        &mut writer,
+                        Arc::clone(&is_cancel),
+                    )
+                })?;
+                i = j;
+            }
+        }
+    }
+    writer.flush().map_err(|_| SyncError::CreateFileFailed)?;
+    Ok(())
+}
+
+// counts everything written to the part file, reused local blocks and downloaded ranges alike
+struct ProgressWriter<'a, W: Write> {
+    inner: W,
+    progress: &'a TaskProgress,
+}
+
+impl<'a, W: Write> Write for ProgressWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.progress.add(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn check_block_checksums(task: &SyncTask, checksums: &BlockChecksumsVo) -> Result<(), SyncError> {
+    let block_size = checksums.block_size;
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        warn!(
+            "invalid block size: {}, file_info: {:?}",
+            block_size, task.file_info
+        );
+        return Err(SyncError::DeltaNotAvailable);
+    }
+    let block_count = task.file_info.size.div_ceil(block_size);
+    if block_count != checksums.blocks.len() as u64 {
+        warn!(
+            "block count not match, expected: {}, actual: {}, file_info: {:?}",
+            block_count,
+            checksums.blocks.len(),
+            task.file_info
+        );
+        return Err(SyncError::DeltaNotAvailable);
+    }
+    Ok(())
+}
+
+// download [start, end) of the file into writer, written is kept between retries so that
+// another data node continues from where the failed one stopped
+fn download_range<W: Write>(
+    data_node_selector: &DataNodeSelector,
+    index: usize,
+    url: &str,
+    range: Range<u64>,
+    written: &mut u64,
+    writer: &mut W,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<(), SyncError> {
+    let (start, end) = (range.start, range.end);
+    let from = start + *written;
+    let start_time = Instant::now();
+    let resp = reqwest::blocking::Client::new()
+        .get(url)
+        .header(header::RANGE, format!("bytes={}-{}", from, end - 1))
+        .send()
+        .map_err(|_| SyncError::DownloadFailed)?;
+    data_node_selector.report_latency(index, start_time.elapsed());
+    if resp.status() != StatusCode::PARTIAL_CONTENT || get_content_range_start(&resp) != Some(from)
+    {
+        warn!(
+            "range request not supported, status: {}, url: {}",
+            resp.status(),
+            url
+        );
+        return Err(SyncError::RangeNotSupported);
+    }
+
+    let mut reader = RateLimitedReader::new(resp, &DOWNLOAD_RATE_LIMITER);
+    let mut buf = vec![0; READ_SIZE];
+    while start + *written < end {
+        if is_cancel.load(Ordering::Relaxed) {
+            return Err(SyncError::Cancel);
+        }
+        let max = (end - start - *written).min(READ_SIZE as u64) as usize;
+        let n = reader
+            .read(&mut buf[..max])
+            .map_err(|_| SyncError::ReadDownloadContentFailed)?;
+        if n == 0 {
+            return Err(SyncError::ReadDownloadContentFailed);
+        }
+        writer
+            .write_all(&buf[..n])
+            .map_err(|_| SyncError::WriteDownloadContentFailed)?;
+        *written += n as u64;
+    }
+    Ok(())
+}
+
+// find the offset in the local file of every server block, a rolling checksum is used so
+// that blocks moved by inserted or removed data can still be found. The strong checksums are
+// made with the hash algorithm of the manifest.
+fn find_local_blocks(
+    local_file_path: &PathBuf,
+    file_size: u64,
+    checksums: &BlockChecksumsVo,
+    hash_algorithm: HashAlgorithm,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<Vec<Option<u64>>, SyncError> {
+    let block_size = checksums.block_size as usize;
+    let blocks = &checksums.blocks;
+    let mut local_offsets: Vec<Option<u64>> = vec![None; blocks.len()];
+    if blocks.is_empty() {
+        return Ok(local_offsets);
+    }
+
+    // the last block may be shorter, it is checked separately
+    let last_block_size = (file_size - (blocks.len() - 1) as u64 * block_size as u64) as usize;
+    let mut full_block_count = blocks.len();
+    if last_block_size < block_size {
+        full_block_count -= 1;
+    }
+
+    let mut weak_index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, x) in blocks.iter().enumerate().take(full_block_count) {
+        weak_index.entry(x.weak).or_default().push(i);
+    }
+
+    let f = fs::File::open(local_file_path).map_err(|_| SyncError::ReadLocalFileFailed)?;
+    let local_file_size = f
+        .metadata()
+        .map_err(|_| SyncError::ReadLocalFileFailed)?
+        .len();
+    let mut window = Window::new(f, is_cancel);
+    let mut remain = full_block_count;
+    if remain > 0 && window.fill(block_size)? {
+        let mut checksum = RollingChecksum::new(window.get(block_size));
+        loop {
+            let mut is_matched = false;
+            if let Some(candidates) = weak_index.get(&checksum.value()) {
+                let strong = hash_algorithm.hash_bytes(window.get(block_size));
+                for i in candidates {
+                    if local_offsets[*i].is_none() && blocks[*i].strong == strong {
+                        local_offsets[*i] = Some(window.offset);
+                        remain -= 1;
+                        is_matched = true;
+                    }
+                }
+            }
+            if remain == 0 {
+                break;
+            }
+
+            if is_matched {
+                window.advance(block_size);
+                if !window.fill(block_size)? {
+                    break;
+                }
+                checksum = RollingChecksum::new(window.get(block_size));
+            } else {
+                if !window.fill(block_size + 1)? {
+                    break;
+                }
+                let w = window.get(block_size + 1);
+                checksum.roll(w[0], w[block_size]);
+                window.advance(1);
+            }
+        }
+    }
+
+    // last short block, at the same position or at the end of the local file
+    if full_block_count < blocks.len() {
+        let i = blocks.len() - 1;
+        let len = last_block_size as u64;
+        let mut positions = vec![i as u64 * block_size as u64];
+        if local_file_size >= len {
+            positions.push(local_file_size - len);
+        }
+        let mut f = fs::File::open(local_file_path).map_err(|_| SyncError::ReadLocalFileFailed)?;
+        let mut buf = vec![0; last_block_size];
+        for p in positions {
+            if p + len > local_file_size {
+                continue;
+            }
+            f.seek(SeekFrom::Start(p))
+                .and_then(|_| f.read_exact(&mut buf))
+                .map_err(|_| SyncError::ReadLocalFileFailed)?;
+            if hash_algorithm.hash_bytes(&buf) == blocks[i].strong {
+                local_offsets[i] = Some(p);
+                break;
+            }
+        }
+    }
+
+    Ok(local_offsets)
+}
+
+// sliding window over a file, only the bytes not yet passed are kept in memory
+struct Window {
+    file: fs::File,
+    buf: Vec<u8>,
+    start: usize,
+    offset: u64,
+    is_cancel: Arc<AtomicBool>,
+}
+
+impl Window {
+    fn new(file: fs::File, is_cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            file,
+            buf: vec![],
+            start: 0,
+            offset: 0,
+            is_cancel,
+        }
+    }
+
+    // make sure len bytes are available from the current offset, false on end of file
+    fn fill(&mut self, len: usize) -> Result<bool, SyncError> {
+        while self.buf.len() - self.start < len {
+            if self.is_cancel.load(Ordering::Relaxed) {
+                return Err(SyncError::Cancel);
+            }
+            self.buf.drain(..self.start);
+            self.start = 0;
+            let l = self.buf.len();
+            self.buf.resize(l + READ_SIZE, 0);
+            let n = self
+                .file
+                .read(&mut self.buf[l..])
+                .map_err(|_| SyncError::ReadLocalFileFailed)?;
+            self.buf.truncate(l + n);
+            if n == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn get(&self, len: usize) -> &[u8] {
+        &self.buf[self.start..self.start + len]
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.start += n;
+        self.offset += n as u64;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::delta::find_local_blocks;
+    use crate::request::data_node::block_checksum::{BlockChecksum, BlockChecksumsVo};
+    use crate::test_util::TempDir;
+    use std::fs;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use util::hash::rolling::rolling_checksum;
+    use util::hash::HashAlgorithm;
+
+    fn get_checksums(
+        data: &[u8],
+        block_size: usize,
+        hash_algorithm: HashAlgorithm,
+    ) -> BlockChecksumsVo {
+        BlockChecksumsVo {
+            block_size: block_size as u64,
+            blocks: data
+                .chunks(block_size)
+                .map(|x| BlockChecksum {
+                    weak: rolling_checksum(x),
+                    strong: hash_algorithm.hash_bytes(x),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_find_local_blocks_shifted() {
+        let temp_dir = TempDir::new("delta");
+        let block_size = 64;
+        // no block repeats, so every block has one place to be found
+        let mut seed: u32 = 1;
+        let data = (0..block_size * 10 + 20)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect::<Vec<u8>>();
+        // 3 bytes inserted before the old content, and one block changed
+        let mut local_data = vec![1, 2, 3];
+        local_data.extend_from_slice(&data);
+        local_data[3 + block_size * 4] ^= 0xff;
+        let local_file_path = temp_dir.path().join("local");
+        fs::write(&local_file_path, &local_data).unwrap();
+
+        for hash_algorithm in [HashAlgorithm::Md5, HashAlgorithm::Sha256] {
+            let checksums = get_checksums(&data, block_size, hash_algorithm);
+            let local_offsets = find_local_blocks(
+                &local_file_path,
+                data.len() as u64,
+                &checksums,
+                hash_algorithm,
+                Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap();
+            assert_eq!(local_offsets.len(), 11);
+            for (i, x) in local_offsets.iter().enumerate() {
+                if i == 4 {
+                    assert_eq!(*x, None);
+                } else {
+                    assert_eq!(*x, Some(3 + (i * block_size) as u64));
+                }
+            }
+        }
+    }
+}
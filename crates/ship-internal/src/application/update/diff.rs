@@ -0,0 +1,254 @@
+use crate::application::update::metadata;
+use crate::types::common::FileInfo;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CasePolicy {
+    Sensitive,
+    // for file systems where a and A are the same file
+    Insensitive,
+}
+
+impl CasePolicy {
+    // policy of the file system of the current platform
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            Self::Insensitive
+        } else {
+            Self::Sensitive
+        }
+    }
+}
+
+// both / and \ are separators, empty and . components are ignored, so that paths from a server
+// on another platform match local paths
+pub fn normalize_path(relative_path: &str, case_policy: CasePolicy) -> String {
+    let normalized = relative_path
+        .split(['/', '\\'])
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect::<Vec<&str>>()
+        .join("/");
+    match case_policy {
+        CasePolicy::Sensitive => normalized,
+        CasePolicy::Insensitive => normalized.to_lowercase(),
+    }
+}
+
+// returns (added_files, changed_files, deleted_files, metadata_changed_files), paths are
+// normalized once and looked up by hash maps, files keep the order of the lists they come from.
+// Files with the same content but another mode are metadata changed, they are not downloaded.
+pub fn diff_files(
+    client_files: &[FileInfo],
+    server_files: &[FileInfo],
+    case_policy: CasePolicy,
+) -> (Vec<FileInfo>, Vec<FileInfo>, Vec<FileInfo>, Vec<FileInfo>) {
+    let mut added_files: Vec<FileInfo> = vec![];
+    let mut changed_files: Vec<FileInfo> = vec![];
+    let mut deleted_files: Vec<FileInfo> = vec![];
+    let mut metadata_changed_files: Vec<FileInfo> = vec![];
+
+    // the first one wins if a path is listed more than once
+    let mut client_index: HashMap<String, &FileInfo> = HashMap::with_capacity(client_files.len());
+    for cf in client_files {
+        client_index
+            .entry(normalize_path(&cf.relative_path, case_policy))
+            .or_insert(cf);
+    }
+    let mut server_paths: HashSet<String> = HashSet::with_capacity(server_files.len());
+
+    for sf in server_files {
+        let sf_path = normalize_path(&sf.relative_path, case_policy);
+        match client_index.get(&sf_path) {
+            None => added_files.push(sf.clone()),
+            Some(cf) => {
+                if cf.file_type != sf.file_type || cf.size != sf.size || cf.hash != sf.hash {
+                    changed_files.push(sf.clone());
+                } else if metadata::is_mode_changed(cf, sf) {
+                    metadata_changed_files.push(sf.clone());
+                }
+            }
+        }
+        server_paths.insert(sf_path);
+    }
+    for cf in client_files {
+        if !server_paths.contains(&normalize_path(&cf.relative_path, case_policy)) {
+            deleted_files.push(cf.clone());
+        }
+    }
+    (
+        added_files,
+        changed_files,
+        deleted_files,
+        metadata_changed_files,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::diff::{diff_files, normalize_path, CasePolicy};
+    use crate::types::common::{FileInfo, FileType};
+    use std::path::Path;
+    use std::time::Instant;
+    use util::hash::HashAlgorithm;
+
+    // the diff before hash maps were used, compared with every file
+    fn diff_files_by_loop(
+        client_files: &[FileInfo],
+        server_files: &[FileInfo],
+    ) -> (Vec<FileInfo>, Vec<FileInfo>, Vec<FileInfo>) {
+        let is_in = |f: &FileInfo, files: &[FileInfo]| {
+            files
+                .iter()
+                .any(|x| Path::new(&x.relative_path).eq(Path::new(&f.relative_path)))
+        };
+        let mut added_files = vec![];
+        let mut changed_files = vec![];
+        let mut deleted_files = vec![];
+        for cf in client_files {
+            if !is_in(cf, server_files) {
+                deleted_files.push(cf.clone());
+            }
+        }
+        for sf in server_files {
+            if !is_in(sf, client_files) {
+                added_files.push(sf.clone());
+            }
+        }
+        for sf in server_files {
+            for cf in client_files {
+                if Path::new(&cf.relative_path).eq(Path::new(&sf.relative_path)) {
+                    if cf.file_type != sf.file_type || cf.size != sf.size || cf.hash != sf.hash {
+                        changed_files.push(sf.clone());
+                    }
+                    break;
+                }
+            }
+        }
+        (added_files, changed_files, deleted_files)
+    }
+
+    fn file(relative_path: &str, file_type: FileType, size: u64, hash: &str) -> FileInfo {
+        FileInfo::new(relative_path, file_type, size, hash, HashAlgorithm::Md5)
+    }
+
+    // server files in dirs of 100 files, the client misses, changes and adds some of them
+    fn make_manifests(n: usize) -> (Vec<FileInfo>, Vec<FileInfo>) {
+        let mut server_files = vec![];
+        let mut client_files = vec![];
+        for i in 0..n {
+            if i % 100 == 0 {
+                let dir = format!("dir{}", i / 100);
+                server_files.push(file(&dir, FileType::Dir, 0, ""));
+                client_files.push(file(&dir, FileType::Dir, 0, ""));
+            }
+            let path = format!("dir{}/file{}.dat", i / 100, i);
+            let hash = format!("{:032x}", i);
+            server_files.push(file(&path, FileType::File, i as u64, &hash));
+            match i % 10 {
+                // missing
+                0 => {}
+                // changed content
+                1 => client_files.push(file(&path, FileType::File, i as u64, "changed")),
+                // changed size
+                2 => client_files.push(file(&path, FileType::File, i as u64 + 1, &hash)),
+                // changed type
+                3 => client_files.push(file(&path, FileType::Dir, 0, "")),
+                _ => client_files.push(file(&path, FileType::File, i as u64, &hash)),
+            }
+            if i % 13 == 0 {
+                let extra_path = format!("dir{}/extra{}.dat", i / 100, i);
+                client_files.push(file(&extra_path, FileType::File, 1, "extra"));
+            }
+        }
+        // the client lists files in another order
+        client_files.reverse();
+        (client_files, server_files)
+    }
+
+    fn to_paths(files: &[FileInfo]) -> Vec<&str> {
+        files.iter().map(|x| x.relative_path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("a\\b//./c/", CasePolicy::Sensitive), "a/b/c");
+        assert_eq!(
+            normalize_path("Data/A.PAK", CasePolicy::Sensitive),
+            "Data/A.PAK"
+        );
+        assert_eq!(
+            normalize_path("Data\\A.PAK", CasePolicy::Insensitive),
+            "data/a.pak"
+        );
+    }
+
+    #[test]
+    fn test_diff_files_same_as_loop() {
+        let (client_files, server_files) = make_manifests(3000);
+        let (added, changed, deleted, _) =
+            diff_files(&client_files, &server_files, CasePolicy::Sensitive);
+        let (expected_added, expected_changed, expected_deleted) =
+            diff_files_by_loop(&client_files, &server_files);
+        assert_eq!(to_paths(&added), to_paths(&expected_added));
+        assert_eq!(to_paths(&changed), to_paths(&expected_changed));
+        assert_eq!(to_paths(&deleted), to_paths(&expected_deleted));
+        assert_eq!(added.len(), 300);
+        assert_eq!(changed.len(), 900);
+        assert_eq!(deleted.len(), 231);
+    }
+
+    #[test]
+    fn test_diff_files_case_insensitive() {
+        let server_files = vec![file("Data/A.pak", FileType::File, 1, "a")];
+        let client_files = vec![file("data\\a.pak", FileType::File, 1, "a")];
+        let (added, changed, deleted, _) =
+            diff_files(&client_files, &server_files, CasePolicy::Insensitive);
+        assert!(added.is_empty() && changed.is_empty() && deleted.is_empty());
+
+        let (added, _, deleted, _) =
+            diff_files(&client_files, &server_files, CasePolicy::Sensitive);
+        assert_eq!(added.len(), 1);
+        assert_eq!(deleted.len(), 1);
+    }
+
+    #[test]
+    fn test_diff_files_metadata_changed() {
+        let with_mode = |mut x: FileInfo, mode| {
+            x.mode = mode;
+            x
+        };
+        let server_files = vec![
+            with_mode(file("bin/game", FileType::File, 1, "a"), Some(0o755)),
+            with_mode(file("bin/tool", FileType::File, 1, "b"), Some(0o755)),
+            with_mode(file("data.pak", FileType::File, 1, "c"), Some(0o644)),
+        ];
+        let client_files = vec![
+            with_mode(file("bin/game", FileType::File, 1, "a"), Some(0o644)),
+            // the content changed too, downloaded with the new mode
+            with_mode(file("bin/tool", FileType::File, 1, "old"), Some(0o644)),
+            // the mode is not known
+            file("data.pak", FileType::File, 1, "c"),
+        ];
+        let (added, changed, deleted, metadata_changed) =
+            diff_files(&client_files, &server_files, CasePolicy::Sensitive);
+        assert!(added.is_empty() && deleted.is_empty());
+        assert_eq!(to_paths(&changed), vec!["bin/tool"]);
+        assert_eq!(to_paths(&metadata_changed), vec!["bin/game"]);
+    }
+
+    // cargo test --release bench_diff_files -- --ignored
+    #[test]
+    #[ignore]
+    fn bench_diff_files() {
+        let (client_files, server_files) = make_manifests(150_000);
+        let start = Instant::now();
+        let (added, changed, deleted, _) =
+            diff_files(&client_files, &server_files, CasePolicy::native());
+        let elapsed = start.elapsed();
+        assert_eq!(added.len(), 15_000);
+        assert_eq!(changed.len(), 45_000);
+        assert_eq!(deleted.len(), 11_539);
+        // the nested loop took minutes for this, a few seconds is allowed for debug builds
+        assert!(elapsed.as_secs() < 10, "elapsed: {:?}", elapsed);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::application::update::UpdateMode;
+use crate::cache;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+// older records are dropped
+const MAX_RECORDS: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum UpdateResult {
+    Finished,
+    Canceled,
+    // continued by a Resume record
+    Paused,
+    Failed,
+}
+
+// one finished, canceled or failed update of an app server
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpdateRecord {
+    pub app_server_id: u64,
+    pub mode: UpdateMode,
+    // unix timestamps in seconds
+    pub start_time: i64,
+    pub end_time: i64,
+    pub result: UpdateResult,
+    // variant of update::Error if failed
+    pub error: Option<String>,
+    // size of synced files, including those copied from the cache
+    pub transferred_bytes: u64,
+    pub added_files: u64,
+    pub changed_files: u64,
+    pub deleted_files: u64,
+    pub protected_files: u64,
+    // last_scan_finish_time of the server files synced, 0 if not known
+    #[serde(default)]
+    pub server_scan_time: i64,
+}
+
+impl UpdateRecord {
+    pub fn new(app_server_id: u64, mode: UpdateMode) -> Self {
+        Self {
+            app_server_id,
+            mode,
+            start_time: chrono::Utc::now().timestamp(),
+            end_time: 0,
+            result: UpdateResult::Canceled,
+            error: None,
+            transferred_bytes: 0,
+            added_files: 0,
+            changed_files: 0,
+            deleted_files: 0,
+            protected_files: 0,
+            server_scan_time: 0,
+        }
+    }
+}
+
+// oldest first, empty if there is no history or it can not be read
+pub fn load(app_server_id: u64) -> Vec<UpdateRecord> {
+    let r = get_history_file_path(app_server_id).and_then(|p| {
+        if !Path::new(&p).exists() {
+            return None;
+        }
+        let d = fs::read_to_string(&p).ok()?;
+        serde_json::from_str::<Vec<UpdateRecord>>(&d)
+            .map_err(|e| {
+                warn!("deserialize update history failed, path: {}, err: {}", p, e);
+            })
+            .ok()
+    });
+    r.unwrap_or_default()
+}
+
+pub fn get_last_finished(app_server_id: u64) -> Option<UpdateRecord> {
+    load(app_server_id)
+        .into_iter()
+        .rev()
+        .find(|x| x.result == UpdateResult::Finished)
+}
+
+pub fn add(record: UpdateRecord) {
+    let p = match get_history_file_path(record.app_server_id) {
+        Some(p) => p,
+        None => {
+            warn!(
+                "get update history path failed, app_server_id: {}",
+                record.app_server_id
+            );
+            return;
+        }
+    };
+    let mut records = load(record.app_server_id);
+    push(&mut records, record);
+    if let Some(parent) = Path::new(&p).parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let r = serde_json::to_string(&records)
+        .map_err(|e| e.to_string())
+        .and_then(|j| fs::write(&p, j).map_err(|e| e.to_string()));
+    if let Err(e) = r {
+        warn!("save update history failed, path: {}, err: {}", p, e);
+    }
+}
+
+fn push(records: &mut Vec<UpdateRecord>, record: UpdateRecord) {
+    records.push(record);
+    if records.len() > MAX_RECORDS {
+        records.drain(..records.len() - MAX_RECORDS);
+    }
+}
+
+fn get_history_file_path(app_server_id: u64) -> Option<String> {
+    let cache_dir_path = cache::get_cache_dir_path().ok()?;
+    let p = Path::new(&cache_dir_path)
+        .join("update-history")
+        .join(format!("{}.json", app_server_id));
+    Some(p.to_str()?.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::history::{push, UpdateRecord, MAX_RECORDS};
+    use crate::application::update::UpdateMode;
+
+    #[test]
+    fn test_push_truncated() {
+        let mut records = vec![];
+        for i in 0..MAX_RECORDS as i64 + 5 {
+            let mut record = UpdateRecord::new(1, UpdateMode::Update);
+            record.start_time = i;
+            push(&mut records, record);
+        }
+        assert_eq!(records.len(), MAX_RECORDS);
+        // the oldest are dropped, the order is kept
+        assert_eq!(records[0].start_time, 5);
+        assert_eq!(records[MAX_RECORDS - 1].start_time, MAX_RECORDS as i64 + 4);
+    }
+}
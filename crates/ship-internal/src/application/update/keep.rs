@@ -0,0 +1,122 @@
+use crate::types::common::{FileInfo, KeepRule};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use log::{debug, warn};
+use std::path::Path;
+
+// local files matched by keep rules are never deleted by an update, e.g. saves and configs of
+// players, files matched by a no_overwrite rule are not updated either if they exist locally
+pub struct KeepRules {
+    keep: GlobSet,
+    no_overwrite: GlobSet,
+}
+
+impl KeepRules {
+    // invalid patterns are ignored
+    pub fn new(rules: &[KeepRule]) -> Self {
+        let mut keep = GlobSetBuilder::new();
+        let mut no_overwrite = GlobSetBuilder::new();
+        for rule in rules {
+            // * does not match /, ** does
+            let glob_r = GlobBuilder::new(rule.pattern.trim_matches('/'))
+                .literal_separator(true)
+                .build();
+            match glob_r {
+                Ok(glob) => {
+                    if rule.no_overwrite {
+                        no_overwrite.add(glob.clone());
+                    }
+                    keep.add(glob);
+                }
+                Err(e) => {
+                    warn!("invalid keep rule, rule: {:?}, err: {}", rule, e);
+                }
+            }
+        }
+        Self {
+            keep: keep.build().unwrap_or_else(|_| GlobSet::empty()),
+            no_overwrite: no_overwrite.build().unwrap_or_else(|_| GlobSet::empty()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keep.is_empty()
+    }
+
+    pub fn is_kept(&self, relative_path: &str) -> bool {
+        is_match(&self.keep, relative_path)
+    }
+
+    pub fn is_no_overwrite(&self, relative_path: &str) -> bool {
+        is_match(&self.no_overwrite, relative_path)
+    }
+
+    // remove protected files from the diff, the number of them is returned
+    pub fn apply(
+        &self,
+        changed_files: &mut Vec<FileInfo>,
+        deleted_files: &mut Vec<FileInfo>,
+    ) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let before = changed_files.len() + deleted_files.len();
+        deleted_files.retain(|x| !self.is_kept(&x.relative_path));
+        changed_files.retain(|x| !self.is_no_overwrite(&x.relative_path));
+        let protected_files = (before - changed_files.len() - deleted_files.len()) as u64;
+        debug!("protected files by keep rules: {}", protected_files);
+        protected_files
+    }
+}
+
+// a path is matched if itself or one of its parent dirs is matched
+fn is_match(glob_set: &GlobSet, relative_path: &str) -> bool {
+    if glob_set.is_empty() {
+        return false;
+    }
+    let normalized = relative_path.replace('\\', "/");
+    let mut p = Some(Path::new(&normalized));
+    while let Some(x) = p {
+        if x.as_os_str().is_empty() {
+            break;
+        }
+        if glob_set.is_match(x) {
+            return true;
+        }
+        p = x.parent();
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::keep::KeepRules;
+    use crate::types::common::KeepRule;
+
+    fn rule(pattern: &str, no_overwrite: bool) -> KeepRule {
+        KeepRule {
+            pattern: pattern.to_string(),
+            no_overwrite,
+        }
+    }
+
+    #[test]
+    fn test_is_kept() {
+        let rules = KeepRules::new(&[
+            rule("saves", false),
+            rule("*.cfg", true),
+            rule("screenshots/**/*.png", false),
+            rule("[invalid", false),
+        ]);
+        assert!(rules.is_kept("saves"));
+        assert!(rules.is_kept("saves/slot1/data.sav"));
+        assert!(rules.is_kept("saves\\slot1"));
+        assert!(rules.is_kept("user.cfg"));
+        assert!(!rules.is_kept("config/user.cfg"));
+        assert!(rules.is_kept("screenshots/2023/a.png"));
+        assert!(!rules.is_kept("screenshots/a.jpg"));
+        assert!(!rules.is_kept("game.exe"));
+
+        assert!(rules.is_no_overwrite("user.cfg"));
+        assert!(!rules.is_no_overwrite("saves/slot1"));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::types::common::FileInfo;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+// permission bits, the file type bits of st_mode are not compared or set
+const MODE_MASK: u32 = 0o7777;
+
+#[cfg(unix)]
+pub fn get_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & MODE_MASK)
+}
+
+// there are no unix permissions to compare with
+#[cfg(not(unix))]
+pub fn get_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+// the file is changed after it is written, so it must not be hard linked with the cache
+pub fn is_set(file_info: &FileInfo) -> bool {
+    file_info.mode.is_some() || file_info.mtime.is_some()
+}
+
+// a file hard linked from the cache by an older update shares its metadata with the cache and
+// other data dirs, it is replaced by a copy of its own before the metadata is changed
+#[cfg(unix)]
+pub fn unshare(p: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let m = fs::symlink_metadata(p)?;
+    if !m.is_file() || m.nlink() <= 1 {
+        return Ok(());
+    }
+    let mut tmp_path = p.as_os_str().to_owned();
+    tmp_path.push(".ship-unshare");
+    fs::copy(p, &tmp_path)?;
+    fs::rename(&tmp_path, p)
+}
+
+// the link count is not known on stable rust
+#[cfg(not(unix))]
+pub fn unshare(_p: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// a mode only differs if it is known on both sides
+pub fn is_mode_changed(client_file: &FileInfo, server_file: &FileInfo) -> bool {
+    match (client_file.mode, server_file.mode) {
+        (Some(x), Some(y)) => x & MODE_MASK != y & MODE_MASK,
+        _ => false,
+    }
+}
+
+// mode and mtime of the manifest are applied after the file is written, those not set are left
+// as they are. A file hard linked from the cache may be read-only already, the owner sets the
+// mtime through a read-only handle on unix, Windows needs write access.
+pub fn apply(file_info: &FileInfo, p: &Path) -> io::Result<()> {
+    if let Some(mtime) = file_info.mtime {
+        let t = UNIX_EPOCH + Duration::from_secs(u64::try_from(mtime).unwrap_or_default());
+        let f = if cfg!(unix) {
+            fs::File::open(p)?
+        } else {
+            fs::File::options().write(true).open(p)?
+        };
+        f.set_modified(t)?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = file_info.mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(p, fs::Permissions::from_mode(mode & MODE_MASK))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::metadata::{apply, get_mode, is_mode_changed, unshare};
+    use crate::test_util::TempDir;
+    use crate::types::common::{FileInfo, FileType};
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+    use util::hash::HashAlgorithm;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply() {
+        let temp_dir = TempDir::new("metadata");
+        let p = temp_dir.path().join("game");
+        fs::write(&p, "launch").unwrap();
+        let mut file_info = FileInfo::new("bin/game", FileType::File, 6, "", HashAlgorithm::Md5);
+        file_info.mode = Some(0o755);
+        file_info.mtime = Some(1_700_000_000);
+        apply(&file_info, &p).unwrap();
+
+        let m = fs::metadata(&p).unwrap();
+        assert_eq!(get_mode(&m), Some(0o755));
+        assert_eq!(
+            m.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unshare() {
+        let temp_dir = TempDir::new("metadata");
+        let cache_file_path = temp_dir.path().join("cache");
+        let p = temp_dir.path().join("game");
+        fs::write(&cache_file_path, "launch").unwrap();
+        fs::hard_link(&cache_file_path, &p).unwrap();
+        let mut file_info = FileInfo::new("bin/game", FileType::File, 6, "", HashAlgorithm::Md5);
+        file_info.mode = Some(0o700);
+        unshare(&p).unwrap();
+        apply(&file_info, &p).unwrap();
+
+        assert_eq!(fs::read_to_string(&p).unwrap(), "launch");
+        assert_eq!(get_mode(&fs::metadata(&p).unwrap()), Some(0o700));
+        assert_ne!(
+            get_mode(&fs::metadata(&cache_file_path).unwrap()),
+            Some(0o700)
+        );
+    }
+
+    #[test]
+    fn test_is_mode_changed() {
+        let file = |mode| {
+            let mut x = FileInfo::new("bin/game", FileType::File, 6, "", HashAlgorithm::Md5);
+            x.mode = mode;
+            x
+        };
+        assert!(is_mode_changed(&file(Some(0o644)), &file(Some(0o755))));
+        assert!(!is_mode_changed(&file(Some(0o100755)), &file(Some(0o755))));
+        assert!(!is_mode_changed(&file(None), &file(Some(0o755))));
+        assert!(!is_mode_changed(&file(Some(0o644)), &file(None)));
+    }
+}
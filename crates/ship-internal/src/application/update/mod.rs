@@ -1,9 +1,28 @@
+mod check;
+mod data_node;
+mod delta;
+pub mod diff;
+pub mod history;
+mod keep;
+pub mod metadata;
+mod pause;
+mod signature;
+mod space;
+mod staging;
 mod sync;
 mod update;
 pub mod update_manage;
 
+use crate::application::app::AppManager;
+use crate::application::common::get_data_path_by_app_server_id;
+use crate::application::scan::ScanProgress;
+use crate::application::settings::SettingsManager;
+use crate::application::update::data_node::DataNodeStat;
+use crate::application::update::history::UpdateRecord;
 use crate::application::update::sync::SyncTask;
 use crate::application::update::update_manage::UpdateManager;
+use crate::request::DOWNLOAD_RATE_LIMITER;
+use serde::{Deserialize, Serialize};
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{mpsc, Arc, Mutex};
 
@@ -17,22 +36,101 @@ pub enum Error {
     CreateDirFailed,
     GetAppServerFailed,
     GetServerFileInfoFailed,
+    // the last scan of the server failed, its file list can not be trusted
+    ServerScanFailed,
+    // the server did not finish scanning within update.server_scan_timeout
+    ServerScanTimeout,
+    VerifySignatureFailed,
+    // no public key is configured for the app, the config key to set is kept, e.g.
+    // apps.<app_code>.public_keys
+    NoPublicKey(String),
     GetClientFileInfoFailed,
     GetDataNodesFailed,
     AddSyncTaskFailed,
     HandleSyncTaskFailed,
+    RollbackFailed,
+    CommitFailed,
+    // a task for the same app server or data path is queued or running
+    DuplicateTask,
+    // there is no paused update to resume
+    LoadPausedUpdateFailed,
+    InsufficientDiskSpace(SpaceShortage),
+}
+
+// the free space of a volume is less than an update needs
+#[derive(Clone, Debug)]
+pub struct SpaceShortage {
+    // otherwise the volume of the data dir
+    pub is_cache_volume: bool,
+    pub path: String,
+    pub required_bytes: u64,
+    pub available_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum UpdateMode {
+    Update,
+    // compute the UpdatePlan only
+    Preview,
+    // hash all local files and report the difference only
+    Verify,
+    // hash all local files and sync broken ones, nothing is deleted
+    Repair,
+    // continue the SyncTasks of a paused update
+    Resume,
+}
+
+// queued tasks of a higher priority start first
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum TaskPriority {
+    // started by the background check
+    Background,
+    Normal,
 }
 
 #[derive(Debug)]
 pub enum UpdateTaskControlMessage {
-    Start { app_server_id: u64 },
-    Stop { app_server_id: u64 },
+    Start {
+        app_server_id: u64,
+        priority: TaskPriority,
+    },
+    Stop {
+        app_server_id: u64,
+    },
+    // stop syncing and keep the remaining SyncTasks
+    Pause {
+        app_server_id: u64,
+    },
+    Resume {
+        app_server_id: u64,
+    },
+    // drop a paused update and what it staged
+    Discard {
+        app_server_id: u64,
+    },
+    // compute the UpdatePlan only, nothing is changed
+    Preview {
+        app_server_id: u64,
+    },
+    Confirm {
+        app_server_id: u64,
+    },
+    // compare local files with the server by hash, nothing is changed
+    Verify {
+        app_server_id: u64,
+    },
+    // sync missing and modified files only, extra files are kept
+    Repair {
+        app_server_id: u64,
+    },
 }
 
 #[derive(Debug)]
 pub enum TaskControlMessage {
     Start,
     Stop,
+    Pause,
+    Confirm,
 }
 
 #[derive(Default, Debug)]
@@ -40,27 +138,90 @@ pub enum UpdateTaskStatus {
     #[default]
     Wait,
     GetServerUpdateInfo,
-    GetClientFileInfo,
+    // the server is still scanning its files, the file list would be partial
+    WaitServerScan {
+        waited_secs: u64,
+    },
+    GetClientFileInfo {
+        progress: ScanProgress,
+    },
     Processing {
         progress: Progress,
         sync_task: SyncTask,
+        data_node_stats: Vec<DataNodeStat>,
+    },
+    // waiting for a Confirm or Stop control message
+    AwaitingConfirmation {
+        plan: UpdatePlan,
+    },
+    // result of a preview
+    Planned {
+        plan: UpdatePlan,
     },
+    // result of a verification
+    Verified {
+        report: VerifyReport,
+    },
+    // the remaining SyncTasks are kept on disk, see pause
+    Paused,
     Canceled,
-    Failed,
+    Failed {
+        // set if the update did not start for lack of disk space
+        space_shortage: Option<SpaceShortage>,
+    },
     Finished {
         finish_time: i64,
+        // local files kept by keep rules
+        protected_files: u64,
     },
 }
 
+// what an update will do, computed before any SyncTask runs
+#[derive(Clone, Default, Debug)]
+pub struct UpdatePlan {
+    pub added_files: u64,
+    pub changed_files: u64,
+    pub deleted_files: Vec<String>,
+    // size of added and changed files, some of them may be copied from the cache instead
+    pub download_bytes: u64,
+    pub protected_files: u64,
+}
+
+// local files not matching the server, by relative path, files protected by keep rules are
+// not included
+#[derive(Clone, Default, Debug)]
+pub struct VerifyReport {
+    pub missing_files: Vec<String>,
+    pub modified_files: Vec<String>,
+    pub extra_files: Vec<String>,
+}
+
+impl VerifyReport {
+    // extra files are not broken, they are not touched by a repair
+    pub fn is_repairable(&self) -> bool {
+        !self.missing_files.is_empty() || !self.modified_files.is_empty()
+    }
+}
+
+// in bytes of added and changed files
 #[derive(Default, Debug)]
 pub struct Progress {
     pub value: u64,
     pub total: u64,
+    // smoothed bytes per second
+    pub speed: u64,
+    // seconds, None until the speed is known
+    pub remaining_time: Option<u64>,
 }
 
 impl Progress {
     pub fn new(value: u64, total: u64) -> Self {
-        Self { value, total }
+        Self {
+            value,
+            total,
+            speed: 0,
+            remaining_time: None,
+        }
     }
 }
 
@@ -69,15 +230,37 @@ pub enum UpdateTaskTraceMessage {
     #[default]
     Wait,
     GetServerUpdateInfo,
-    GetClientFileInfo,
+    // the server is still scanning its files, the file list would be partial
+    WaitServerScan {
+        waited_secs: u64,
+    },
+    GetClientFileInfo {
+        progress: ScanProgress,
+    },
     Processing {
         progress: Progress,
         sync_task: SyncTask,
+        data_node_stats: Vec<DataNodeStat>,
+    },
+    AwaitingConfirmation {
+        plan: UpdatePlan,
+    },
+    Planned {
+        plan: UpdatePlan,
     },
+    Verified {
+        report: VerifyReport,
+    },
+    Paused,
     Canceled,
-    Failed,
+    Failed {
+        // set if the update did not start for lack of disk space
+        space_shortage: Option<SpaceShortage>,
+    },
     Finished {
         finish_time: i64,
+        // local files kept by keep rules
+        protected_files: u64,
     },
 }
 
@@ -85,6 +268,9 @@ pub enum UpdateTaskTraceMessage {
 pub struct UpdateTask {
     pub id: u64,
     pub app_server_id: u64,
+    pub priority: TaskPriority,
+    // empty if not known, then it is checked by app_server_id only
+    pub data_path: String,
     pub status: UpdateTaskStatus,
     pub tx: Sender<TaskControlMessage>,
     rx: Receiver<TaskControlMessage>,
@@ -96,6 +282,8 @@ impl UpdateTask {
         Self {
             id,
             app_server_id,
+            priority: TaskPriority::Normal,
+            data_path: "".to_string(),
             status: Default::default(),
             tx,
             rx,
@@ -105,7 +293,7 @@ impl UpdateTask {
 
 pub fn start_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
     let update_manager_g = update_manager.lock().unwrap();
-    update_manager_g.start_task(app_server_id);
+    update_manager_g.start_task(app_server_id, TaskPriority::Normal);
     drop(update_manager_g);
 }
 
@@ -114,3 +302,124 @@ pub fn stop_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>
     update_manager_g.stop_task(app_server_id);
     drop(update_manager_g);
 }
+
+pub fn pause_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
+    let update_manager_g = update_manager.lock().unwrap();
+    let _ = update_manager_g.pause_task(app_server_id);
+    drop(update_manager_g);
+}
+
+pub fn resume_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
+    let update_manager_g = update_manager.lock().unwrap();
+    let _ = update_manager_g.resume_task(app_server_id);
+    drop(update_manager_g);
+}
+
+pub fn discard_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
+    let update_manager_g = update_manager.lock().unwrap();
+    let _ = update_manager_g.discard_task(app_server_id);
+    drop(update_manager_g);
+}
+
+// updates paused before the last exit are shown as paused, after the app servers are loaded
+pub fn restore_paused_updates(
+    update_manager: Arc<Mutex<UpdateManager>>,
+    app_manager: Arc<Mutex<AppManager>>,
+    settings_manager: Arc<Mutex<SettingsManager>>,
+) {
+    let app_manager_g = app_manager.lock().unwrap();
+    let app_server_ids: Vec<u64> = app_manager_g
+        .apps
+        .values()
+        .flat_map(|x| x.app_server_info.servers.keys().cloned())
+        .collect();
+    drop(app_manager_g);
+    for app_server_id in app_server_ids {
+        let data_path_r = get_data_path_by_app_server_id(
+            app_server_id,
+            Arc::clone(&app_manager),
+            Arc::clone(&settings_manager),
+        );
+        if let Ok(data_path) = data_path_r {
+            if pause::is_paused(&data_path) {
+                let mut update_manager_g = update_manager.lock().unwrap();
+                update_manager_g.add_paused_task(app_server_id);
+                drop(update_manager_g);
+            }
+        }
+    }
+}
+
+pub fn preview_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
+    let update_manager_g = update_manager.lock().unwrap();
+    let _ = update_manager_g.preview_task(app_server_id);
+    drop(update_manager_g);
+}
+
+pub fn confirm_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
+    let update_manager_g = update_manager.lock().unwrap();
+    let _ = update_manager_g.confirm_task(app_server_id);
+    drop(update_manager_g);
+}
+
+pub fn verify_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
+    let update_manager_g = update_manager.lock().unwrap();
+    let _ = update_manager_g.verify_task(app_server_id);
+    drop(update_manager_g);
+}
+
+pub fn repair_update(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
+    let update_manager_g = update_manager.lock().unwrap();
+    let _ = update_manager_g.repair_task(app_server_id);
+    drop(update_manager_g);
+}
+
+// moves a queued update one place to the front or the back of the queue
+pub fn move_queued_update(
+    app_server_id: u64,
+    is_forward: bool,
+    update_manager: Arc<Mutex<UpdateManager>>,
+) {
+    let mut update_manager_g = update_manager.lock().unwrap();
+    update_manager_g.move_queued_task(app_server_id, is_forward);
+    drop(update_manager_g);
+}
+
+// remove the finished preview or verification, so it is not shown any more
+pub fn dismiss_update_result(app_server_id: u64, update_manager: Arc<Mutex<UpdateManager>>) {
+    let mut update_manager_g = update_manager.lock().unwrap();
+    let id_o = update_manager_g
+        .get_update_task_by_app_server_id(app_server_id)
+        .filter(|x| {
+            matches!(
+                x.status,
+                UpdateTaskStatus::Planned { .. } | UpdateTaskStatus::Verified { .. }
+            )
+        })
+        .map(|x| x.id);
+    if let Some(id) = id_o {
+        let _ = update_manager_g.remove_task(id);
+    }
+    drop(update_manager_g);
+}
+
+// updates and repairs of the app server, newest first
+pub fn get_update_history(app_server_id: u64) -> Vec<UpdateRecord> {
+    let mut records = history::load(app_server_id);
+    records.reverse();
+    records
+}
+
+// download_limit is in KiB/s, 0 means unlimited, running downloads are slowed down or sped up
+// from their next read
+pub fn set_download_limit(download_limit: u64, settings_manager: Arc<Mutex<SettingsManager>>) {
+    let mut settings_manager_g = settings_manager.lock().unwrap();
+    settings_manager_g.settings.update_settings.download_limit = download_limit;
+    drop(settings_manager_g);
+    DOWNLOAD_RATE_LIMITER.set_limit(download_limit * 1024);
+}
+
+// KiB/s, 0 means unlimited
+pub fn get_download_limit() -> u64 {
+    DOWNLOAD_RATE_LIMITER.get_limit() / 1024
+}
@@ -0,0 +1,197 @@
+use crate::application::update::history::UpdateRecord;
+use crate::application::update::staging;
+use crate::application::update::sync::{SyncTask, SyncTaskType};
+use crate::types::common::{FileInfo, FileType};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use util::hash::HashAlgorithm;
+
+// the SyncTasks of a paused update are kept in <data_path>.ship-paused, files staged or partly
+// downloaded before the pause are found in the staging area again when it is resumed
+const PAUSED_SUFFIX: &str = ".ship-paused";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PausedUpdate {
+    app_id: u64,
+    hash_algorithm: String,
+    files: Vec<PausedFile>,
+    // copied to the UpdateRecord of the resumed update
+    pub server_scan_time: i64,
+    pub added_files: u64,
+    pub changed_files: u64,
+    pub deleted_files: u64,
+    pub protected_files: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PausedFile {
+    sync_type: SyncTaskType,
+    relative_path: String,
+    file_type: FileType,
+    size: u64,
+    hash: String,
+    #[serde(default)]
+    mode: Option<u32>,
+    #[serde(default)]
+    mtime: Option<i64>,
+}
+
+impl PausedUpdate {
+    pub fn new(sync_tasks: &[SyncTask], record: &UpdateRecord) -> Self {
+        let (app_id, hash_algorithm) = sync_tasks
+            .first()
+            .map(|x| (x.app_id, x.file_info.hash_algorithm))
+            .unwrap_or_default();
+        Self {
+            app_id,
+            hash_algorithm: hash_algorithm.name().to_string(),
+            files: sync_tasks
+                .iter()
+                .map(|x| PausedFile {
+                    sync_type: x.sync_type.clone(),
+                    relative_path: x.file_info.relative_path.clone(),
+                    file_type: x.file_info.file_type.clone(),
+                    size: x.file_info.size,
+                    hash: x.file_info.hash.clone(),
+                    mode: x.file_info.mode,
+                    mtime: x.file_info.mtime,
+                })
+                .collect(),
+            server_scan_time: record.server_scan_time,
+            added_files: record.added_files,
+            changed_files: record.changed_files,
+            deleted_files: record.deleted_files,
+            protected_files: record.protected_files,
+        }
+    }
+
+    // None if the hash algorithm is not supported any more
+    pub fn to_sync_tasks(&self, data_path: &str) -> Option<Vec<SyncTask>> {
+        let hash_algorithm = HashAlgorithm::from_name(&self.hash_algorithm)?;
+        let staging_path = staging::get_staging_path(data_path);
+        let sync_tasks = self
+            .files
+            .iter()
+            .map(|x| {
+                let mut file_info = FileInfo::new(
+                    &x.relative_path,
+                    x.file_type.clone(),
+                    x.size,
+                    &x.hash,
+                    hash_algorithm,
+                );
+                file_info.mode = x.mode;
+                file_info.mtime = x.mtime;
+                SyncTask::new(
+                    self.app_id,
+                    x.sync_type.clone(),
+                    file_info,
+                    data_path.to_string(),
+                    staging_path.clone(),
+                )
+            })
+            .collect();
+        Some(sync_tasks)
+    }
+}
+
+fn get_paused_path(data_path: &str) -> String {
+    format!("{}{}", data_path, PAUSED_SUFFIX)
+}
+
+pub fn is_paused(data_path: &str) -> bool {
+    Path::new(&get_paused_path(data_path)).exists()
+}
+
+pub fn save(data_path: &str, paused_update: &PausedUpdate) {
+    let p = get_paused_path(data_path);
+    let r = serde_json::to_string(paused_update)
+        .map_err(|e| e.to_string())
+        .and_then(|j| fs::write(&p, j).map_err(|e| e.to_string()));
+    match r {
+        Ok(_) => debug!("paused update saved, path: {}", p),
+        Err(e) => warn!("save paused update failed, path: {}, err: {}", p, e),
+    }
+}
+
+pub fn load(data_path: &str) -> Option<PausedUpdate> {
+    let p = get_paused_path(data_path);
+    let d = fs::read_to_string(&p).ok()?;
+    serde_json::from_str::<PausedUpdate>(&d)
+        .map_err(|e| {
+            warn!("deserialize paused update failed, path: {}, err: {}", p, e);
+        })
+        .ok()
+}
+
+// the paused update is replaced by a new one, or finished
+pub fn remove(data_path: &str) {
+    let p = get_paused_path(data_path);
+    if !Path::new(&p).exists() {
+        return;
+    }
+    if let Err(e) = fs::remove_file(&p) {
+        warn!("remove paused update failed, path: {}, err: {}", p, e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::history::UpdateRecord;
+    use crate::application::update::pause::PausedUpdate;
+    use crate::application::update::staging;
+    use crate::application::update::sync::{SyncTask, SyncTaskType};
+    use crate::application::update::UpdateMode;
+    use crate::types::common::{FileInfo, FileType};
+    use util::hash::HashAlgorithm;
+
+    #[test]
+    fn test_paused_update() {
+        let data_path = "/data/app";
+        let staging_path = staging::get_staging_path(data_path);
+        let mut file_info =
+            FileInfo::new("bin/game", FileType::File, 3, "abc", HashAlgorithm::Blake3);
+        file_info.mode = Some(0o755);
+        let sync_tasks = vec![
+            SyncTask::new(
+                7,
+                SyncTaskType::Create,
+                file_info,
+                data_path.to_string(),
+                staging_path.clone(),
+            ),
+            SyncTask::new(
+                7,
+                SyncTaskType::Delete,
+                FileInfo::new("old", FileType::Dir, 0, "", HashAlgorithm::Blake3),
+                data_path.to_string(),
+                staging_path,
+            ),
+        ];
+        let mut record = UpdateRecord::new(1, UpdateMode::Update);
+        record.added_files = 1;
+        record.deleted_files = 1;
+
+        let j = serde_json::to_string(&PausedUpdate::new(&sync_tasks, &record)).unwrap();
+        let paused_update: PausedUpdate = serde_json::from_str(&j).unwrap();
+        assert_eq!(paused_update.added_files, 1);
+        assert_eq!(paused_update.deleted_files, 1);
+
+        let restored = paused_update.to_sync_tasks(data_path).unwrap();
+        assert_eq!(restored.len(), 2);
+        for (x, y) in restored.iter().zip(&sync_tasks) {
+            assert_eq!(x.app_id, y.app_id);
+            assert_eq!(x.file_info.relative_path, y.file_info.relative_path);
+            assert_eq!(x.file_info.file_type, y.file_info.file_type);
+            assert_eq!(x.file_info.size, y.file_info.size);
+            assert_eq!(x.file_info.hash, y.file_info.hash);
+            assert_eq!(x.file_info.hash_algorithm, y.file_info.hash_algorithm);
+            assert_eq!(x.file_info.mode, y.file_info.mode);
+            assert_eq!(x.base_path, y.base_path);
+            assert_eq!(x.staging_path, y.staging_path);
+        }
+        assert!(matches!(restored[1].sync_type, SyncTaskType::Delete));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::config::AppConfig;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use log::warn;
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    NoPublicKey,
+    SignatureMissing,
+    SignatureInvalid,
+}
+
+// the manifest is signed by Ed25519 over the exact body sent by the server, the signature is
+// accepted if it is made by any of the public keys of the app, so that keys can be rotated
+pub fn verify_manifest(
+    app_config: Option<&AppConfig>,
+    body: &[u8],
+    signature: Option<&str>,
+) -> Result<(), SignatureError> {
+    let (public_keys, is_unsigned_allowed) = match app_config {
+        None => (&[][..], false),
+        Some(x) => (&x.public_keys[..], x.allow_unsigned),
+    };
+    if is_unsigned_allowed {
+        return Ok(());
+    }
+
+    let verifying_keys: Vec<VerifyingKey> = public_keys
+        .iter()
+        .filter_map(|x| match parse_public_key(x) {
+            Some(key) => Some(key),
+            None => {
+                warn!("invalid public key: {}", x);
+                None
+            }
+        })
+        .collect();
+    if verifying_keys.is_empty() {
+        return Err(SignatureError::NoPublicKey);
+    }
+
+    let signature = match signature {
+        None => return Err(SignatureError::SignatureMissing),
+        Some(x) => parse_signature(x).ok_or(SignatureError::SignatureInvalid)?,
+    };
+    if verifying_keys
+        .iter()
+        .any(|x| x.verify(body, &signature).is_ok())
+    {
+        Ok(())
+    } else {
+        Err(SignatureError::SignatureInvalid)
+    }
+}
+
+// hex of the 32 bytes key
+fn parse_public_key(s: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(s.trim()).ok()?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+// hex of the 64 bytes signature
+fn parse_signature(s: &str) -> Option<Signature> {
+    let bytes: [u8; 64] = hex::decode(s.trim()).ok()?.try_into().ok()?;
+    Some(Signature::from_bytes(&bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::signature::{verify_manifest, SignatureError};
+    use crate::config::AppConfig;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_verify_manifest() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        let body = br#"{"status":2,"files":[]}"#;
+        let signature = hex::encode(signing_key.sign(body).to_bytes());
+        let app_config = AppConfig {
+            public_keys: vec![
+                hex::encode(other_key.verifying_key().to_bytes()),
+                hex::encode(signing_key.verifying_key().to_bytes()),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            verify_manifest(Some(&app_config), body, Some(&signature)),
+            Ok(())
+        );
+        assert_eq!(
+            verify_manifest(Some(&app_config), b"{}", Some(&signature)),
+            Err(SignatureError::SignatureInvalid)
+        );
+        assert_eq!(
+            verify_manifest(Some(&app_config), body, Some("00")),
+            Err(SignatureError::SignatureInvalid)
+        );
+        assert_eq!(
+            verify_manifest(Some(&app_config), body, None),
+            Err(SignatureError::SignatureMissing)
+        );
+        assert_eq!(
+            verify_manifest(None, body, Some(&signature)),
+            Err(SignatureError::NoPublicKey)
+        );
+
+        let app_config = AppConfig {
+            allow_unsigned: true,
+            ..Default::default()
+        };
+        assert_eq!(verify_manifest(Some(&app_config), body, None), Ok(()));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::application::update::metadata;
+use crate::application::update::sync::{get_part_file_path, SyncTask, SyncTaskType};
+use crate::application::update::SpaceShortage;
+use crate::cache;
+use crate::cache::CachedFiles;
+use crate::config::{CacheLinkMode, CONFIG};
+use crate::types::common::FileType;
+use log::{debug, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// bytes written by the SyncTasks if files are copied, files found in the cache are copied to the
+// staging area but not downloaded, so they take no space in the cache
+#[derive(Default, PartialEq, Debug)]
+pub struct RequiredSpace {
+    // staged files, they replace the files in the data dir on commit by renaming
+    pub data_bytes: u64,
+    // downloaded files are added to the cache
+    pub cache_bytes: u64,
+    // part of data_bytes taken from the cache and linked if allowed
+    pub cached_bytes: u64,
+    // part of cache_bytes always copied to the cache, see metadata::is_set
+    pub copied_cache_bytes: u64,
+}
+
+pub fn get_required_space(sync_tasks: &[SyncTask], cached_files: &CachedFiles) -> RequiredSpace {
+    let mut required_space = RequiredSpace::default();
+    for task in sync_tasks {
+        if matches!(
+            task.sync_type,
+            SyncTaskType::Delete | SyncTaskType::UpdateMetadata
+        ) || task.file_info.file_type != FileType::File
+        {
+            continue;
+        }
+        let size = task.file_info.size;
+        let staged_file_path = Path::new(&task.staging_path).join(&task.file_info.relative_path);
+        // staged by an update that failed, was canceled or paused
+        if get_file_size(&staged_file_path) == Some(size) {
+            continue;
+        }
+        let is_hard_link_allowed = !metadata::is_set(&task.file_info);
+        if cached_files.contains(task.file_info.hash_algorithm, &task.file_info.hash) {
+            required_space.data_bytes += size;
+            if is_hard_link_allowed {
+                required_space.cached_bytes += size;
+            }
+            continue;
+        }
+        // the download is resumed from the part file
+        let part_size = get_file_size(&get_part_file_path(&staged_file_path)).unwrap_or(0);
+        required_space.data_bytes += size.saturating_sub(part_size);
+        required_space.cache_bytes += size;
+        if !is_hard_link_allowed {
+            required_space.copied_cache_bytes += size;
+        }
+    }
+    required_space
+}
+
+// the data dir and the cache may be on the same volume, then both are taken from it
+pub fn check_free_space(
+    data_path: &str,
+    required_space: &RequiredSpace,
+) -> Result<(), SpaceShortage> {
+    let cache_dir_path = match cache::get_update_cache_dir_path() {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("get update cache dir failed, err: {:?}", e);
+            return Ok(());
+        }
+    };
+    let data_volume_path = get_existing_ancestor(Path::new(data_path));
+    let cache_volume_path = get_existing_ancestor(Path::new(&cache_dir_path));
+    let mut data_required_bytes = required_space.data_bytes;
+    let mut cache_required_bytes = required_space.cache_bytes;
+    if is_same_volume(&data_volume_path, &cache_volume_path) {
+        // the file system may have no hard links, then files are copied like across volumes
+        let is_linked = CONFIG.update.cache_link == CacheLinkMode::Auto
+            && is_hard_link_supported(&cache_volume_path);
+        if is_linked {
+            // hard linked both ways, unless reflinked, which takes no space either
+            data_required_bytes -= required_space.cached_bytes;
+            data_required_bytes += required_space.copied_cache_bytes;
+        } else {
+            data_required_bytes += cache_required_bytes;
+        }
+        cache_required_bytes = 0;
+    }
+
+    for (path, required_bytes, is_cache_volume) in [
+        (data_volume_path, data_required_bytes, false),
+        (cache_volume_path, cache_required_bytes, true),
+    ] {
+        if required_bytes == 0 {
+            continue;
+        }
+        let available_bytes = match fs2::available_space(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                // not known on some file systems, the update goes on as before
+                warn!("get free space failed, path: {:?}, err: {}", path, e);
+                continue;
+            }
+        };
+        debug!(
+            "free space, path: {:?}, required: {}, available: {}",
+            path, required_bytes, available_bytes
+        );
+        if available_bytes < required_bytes {
+            return Err(SpaceShortage {
+                is_cache_volume,
+                path: path.to_string_lossy().to_string(),
+                required_bytes,
+                available_bytes,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn get_file_size(p: &Path) -> Option<u64> {
+    match fs::symlink_metadata(p) {
+        Ok(m) if m.is_file() => Some(m.len()),
+        _ => None,
+    }
+}
+
+// the dir may not be created yet
+fn get_existing_ancestor(p: &Path) -> PathBuf {
+    p.ancestors()
+        .find(|x| x.exists())
+        .unwrap_or(p)
+        .to_path_buf()
+}
+
+// tried by linking a file in the dir, false if it can not be written either
+fn is_hard_link_supported(dir_path: &Path) -> bool {
+    let src_path = dir_path.join(".ship-link-test");
+    let dst_path = dir_path.join(".ship-link-test-2");
+    let _ = fs::remove_file(&dst_path);
+    let r = fs::write(&src_path, []).and_then(|_| fs::hard_link(&src_path, &dst_path));
+    let _ = fs::remove_file(&src_path);
+    let _ = fs::remove_file(&dst_path);
+    if let Err(e) = &r {
+        debug!("hard link not supported, path: {:?}, err: {}", dir_path, e);
+    }
+    r.is_ok()
+}
+
+#[cfg(unix)]
+fn is_same_volume(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(x), Ok(y)) => x.dev() == y.dev(),
+        _ => false,
+    }
+}
+
+// by drive or share, a.k.a. the path prefix
+#[cfg(not(unix))]
+fn is_same_volume(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(x), Ok(y)) => x.components().next() == y.components().next(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::space::{
+        get_required_space, is_hard_link_supported, RequiredSpace,
+    };
+    use crate::application::update::sync::{get_part_file_path, SyncTask, SyncTaskType};
+    use crate::cache::CachedFiles;
+    use crate::test_util::TempDir;
+    use crate::types::common::{FileInfo, FileType};
+    use std::fs;
+    use std::path::Path;
+    use util::hash::HashAlgorithm;
+
+    #[test]
+    fn test_get_required_space() {
+        let temp_dir = TempDir::new("space");
+        let data_path = temp_dir.join("data");
+        let staging_path = temp_dir.join("staging");
+        fs::create_dir_all(&staging_path).unwrap();
+        // staged completely, and downloaded partly
+        fs::write(Path::new(&staging_path).join("staged"), vec![0u8; 10]).unwrap();
+        fs::write(
+            get_part_file_path(&Path::new(&staging_path).join("part")),
+            vec![0u8; 30],
+        )
+        .unwrap();
+
+        let make_task = |sync_type: SyncTaskType, relative_path: &str, file_type, size| {
+            SyncTask::new(
+                1,
+                sync_type,
+                FileInfo::new(
+                    relative_path,
+                    file_type,
+                    size,
+                    &format!("{:064x}", size),
+                    HashAlgorithm::Sha256,
+                ),
+                data_path.clone(),
+                staging_path.clone(),
+            )
+        };
+        let mut sync_tasks = vec![
+            make_task(SyncTaskType::Create, "new", FileType::File, 100),
+            make_task(SyncTaskType::Update, "staged", FileType::File, 10),
+            make_task(SyncTaskType::Update, "part", FileType::File, 50),
+            make_task(SyncTaskType::Create, "dir", FileType::Dir, 0),
+            make_task(SyncTaskType::Delete, "old", FileType::File, 1000),
+            make_task(SyncTaskType::Create, "cached", FileType::File, 70),
+        ];
+        let mut cached_files = CachedFiles::default();
+        cached_files.insert(HashAlgorithm::Sha256, &format!("{:064x}", 70));
+        // copied to the cache, its mode is set after
+        let mut exec_task = make_task(SyncTaskType::Create, "exec", FileType::File, 40);
+        exec_task.file_info.mode = Some(0o755);
+        sync_tasks.push(exec_task);
+        let required_space = get_required_space(&sync_tasks, &cached_files);
+        assert_eq!(
+            required_space,
+            RequiredSpace {
+                data_bytes: 100 + 20 + 70 + 40,
+                cache_bytes: 100 + 50 + 40,
+                cached_bytes: 70,
+                copied_cache_bytes: 40,
+            }
+        );
+    }
+
+    #[test]
+    fn test_is_hard_link_supported() {
+        let temp_dir = TempDir::new("space");
+        assert!(is_hard_link_supported(temp_dir.path()));
+        // nothing is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        assert!(!is_hard_link_supported(&temp_dir.path().join("missing")));
+    }
+}
@@ -0,0 +1,573 @@
+use crate::application::update::diff::CasePolicy;
+use crate::application::update::sync::{SyncTask, SyncTaskType};
+use crate::application::update::{diff, sync};
+use crate::types::common::FileType;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// new content of an update is written to <data_path>.ship-staging, and moved into the data
+// path only after every SyncTask succeeded, replaced and deleted files are moved to
+// <data_path>.ship-backup. The journal lists every step of the switch, so an interrupted
+// switch can be rolled back on next start.
+const STAGING_SUFFIX: &str = ".ship-staging";
+const BACKUP_SUFFIX: &str = ".ship-backup";
+pub const JOURNAL_SUFFIX: &str = ".ship-journal";
+
+#[derive(Debug)]
+pub enum StagingError {
+    PathInvalid,
+    WriteJournalFailed,
+    ReadJournalFailed,
+    RemoveJournalFailed,
+    CreateDirFailed,
+    BackupFailed,
+    MoveStagedFileFailed,
+    RestoreFailed,
+    CleanFailed,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JournalEntry {
+    relative_path: String,
+    action: JournalAction,
+    // whether something was at the path in the data dir before the switch
+    is_existed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum JournalAction {
+    // move the staged file or symlink into the data dir
+    Write,
+    CreateDir,
+    Delete,
+    DeleteDir,
+}
+
+pub fn get_staging_path(data_path: &str) -> String {
+    format!("{}{}", data_path, STAGING_SUFFIX)
+}
+
+fn get_backup_path(data_path: &str) -> String {
+    format!("{}{}", data_path, BACKUP_SUFFIX)
+}
+
+fn get_journal_path(data_path: &str) -> String {
+    format!("{}{}", data_path, JOURNAL_SUFFIX)
+}
+
+pub fn commit(data_path: &str, sync_tasks: &[SyncTask]) -> Result<(), StagingError> {
+    let journal = make_journal(data_path, sync_tasks);
+    debug!(
+        "commit staging, data_path: {}, journal: {:?}",
+        data_path, journal
+    );
+
+    let backup_path = get_backup_path(data_path);
+    remove_path(Path::new(&backup_path)).map_err(|_| StagingError::CleanFailed)?;
+    fs::create_dir_all(&backup_path).map_err(|_| StagingError::CreateDirFailed)?;
+
+    let j = serde_json::to_string(&journal).map_err(|_| StagingError::WriteJournalFailed)?;
+    fs::write(get_journal_path(data_path), j).map_err(|_| StagingError::WriteJournalFailed)?;
+
+    for (i, entry) in journal.entries.iter().enumerate() {
+        if let Err(e) = apply_entry(data_path, i, entry) {
+            warn!(
+                "commit staging failed, roll back, err: {:?}, entry: {:?}",
+                e, entry
+            );
+            rollback(data_path)?;
+            return Err(e);
+        }
+    }
+
+    // committed, there is nothing to roll back after the journal is removed
+    fs::remove_file(get_journal_path(data_path)).map_err(|_| StagingError::RemoveJournalFailed)?;
+    clean(data_path);
+    info!("staging committed, data_path: {}", data_path);
+    Ok(())
+}
+
+// roll back an interrupted or failed switch, do nothing when there is no journal
+pub fn rollback(data_path: &str) -> Result<(), StagingError> {
+    let journal_path = get_journal_path(data_path);
+    if !Path::new(&journal_path).exists() {
+        return Ok(());
+    }
+    warn!("roll back update, data_path: {}", data_path);
+    let d = fs::read_to_string(&journal_path).map_err(|_| StagingError::ReadJournalFailed)?;
+    let journal = serde_json::from_str::<Journal>(&d).map_err(|e| {
+        warn!("deserialize journal failed, err: {}", e);
+        StagingError::ReadJournalFailed
+    })?;
+
+    for (i, entry) in journal.entries.iter().enumerate().rev() {
+        rollback_entry(data_path, i, entry).map_err(|e| {
+            warn!("roll back failed, err: {:?}, entry: {:?}", e, entry);
+            e
+        })?;
+    }
+
+    fs::remove_file(&journal_path).map_err(|_| StagingError::RemoveJournalFailed)?;
+    let _ = remove_path(Path::new(&get_backup_path(data_path)));
+    info!("roll back finished, data_path: {}", data_path);
+    Ok(())
+}
+
+// drop what a paused or failed update staged, an interrupted switch is rolled back first
+pub fn discard(data_path: &str) -> Result<(), StagingError> {
+    rollback(data_path)?;
+    clean(data_path);
+    info!("staging discarded, data_path: {}", data_path);
+    Ok(())
+}
+
+// roll back every journal left in the data dir, e.g. the program was killed while switching
+pub fn rollback_all(data_dir_path: &str) {
+    // <data_dir>/<app_code>/<app_id>/<app_server_id>/<app_dir_name>.ship-journal
+    let iter = walkdir::WalkDir::new(data_dir_path)
+        .max_depth(4)
+        .into_iter();
+    for entry in iter.flatten() {
+        let p = entry.path().to_string_lossy().to_string();
+        if let Some(data_path) = p.strip_suffix(JOURNAL_SUFFIX) {
+            if let Err(e) = rollback(data_path) {
+                warn!("roll back failed, data_path: {}, err: {:?}", data_path, e);
+            }
+        }
+    }
+}
+
+fn make_journal(data_path: &str, sync_tasks: &[SyncTask]) -> Journal {
+    let mut writes = vec![];
+    let mut create_dirs = vec![];
+    let mut deletes = vec![];
+    let mut delete_dirs = vec![];
+    for task in sync_tasks {
+        let relative_path = task.file_info.relative_path.clone();
+        let p = Path::new(data_path).join(&relative_path);
+        match task.sync_type {
+            SyncTaskType::Create | SyncTaskType::Update => match task.file_info.file_type {
+                FileType::Dir => create_dirs.push(JournalEntry {
+                    relative_path,
+                    action: JournalAction::CreateDir,
+                    is_existed: p.is_dir() && !p.is_symlink(),
+                }),
+                _ => writes.push(JournalEntry {
+                    relative_path,
+                    action: JournalAction::Write,
+                    is_existed: fs::symlink_metadata(&p).is_ok(),
+                }),
+            },
+            // applied to the data dir after the switch, see update::sync_and_commit
+            SyncTaskType::UpdateMetadata => {}
+            SyncTaskType::Delete => {
+                if p.is_dir() && !p.is_symlink() {
+                    delete_dirs.push(JournalEntry {
+                        relative_path,
+                        action: JournalAction::DeleteDir,
+                        is_existed: true,
+                    });
+                } else {
+                    deletes.push(JournalEntry {
+                        relative_path,
+                        action: JournalAction::Delete,
+                        is_existed: fs::symlink_metadata(&p).is_ok(),
+                    });
+                }
+            }
+        }
+    }
+    // whatever was under a written path is moved to the backup with it, e.g. a dir replaced by a
+    // symlink, deleting it would go through the new file
+    let written_paths = writes
+        .iter()
+        .map(|x| diff::normalize_path(&x.relative_path, CasePolicy::Sensitive))
+        .collect::<HashSet<String>>();
+    let is_under_written_path = |x: &JournalEntry| {
+        let normalized = diff::normalize_path(&x.relative_path, CasePolicy::Sensitive);
+        let mut parent = normalized.as_str();
+        while let Some((x, _)) = parent.rsplit_once('/') {
+            if written_paths.contains(x) {
+                return true;
+            }
+            parent = x;
+        }
+        false
+    };
+    deletes.retain(|x| !is_under_written_path(x));
+    delete_dirs.retain(|x| !is_under_written_path(x));
+
+    // parents are created before children, and deleted after them
+    create_dirs.sort_by_key(|x| Path::new(&x.relative_path).components().count());
+    delete_dirs.sort_by_key(|x| usize::MAX - Path::new(&x.relative_path).components().count());
+
+    let mut entries = vec![];
+    entries.append(&mut create_dirs);
+    entries.append(&mut writes);
+    entries.append(&mut deletes);
+    entries.append(&mut delete_dirs);
+    Journal { entries }
+}
+
+fn apply_entry(data_path: &str, index: usize, entry: &JournalEntry) -> Result<(), StagingError> {
+    let target_path = get_path(data_path, &entry.relative_path)?;
+    let backup_path = Path::new(&get_backup_path(data_path)).join(index.to_string());
+    match entry.action {
+        JournalAction::Write => {
+            let staged_path = get_path(&get_staging_path(data_path), &entry.relative_path)?;
+            if fs::symlink_metadata(&target_path).is_ok() {
+                fs::rename(&target_path, &backup_path).map_err(|_| StagingError::BackupFailed)?;
+            }
+            if let Some(parent) = target_path.parent() {
+                fs::create_dir_all(parent).map_err(|_| StagingError::CreateDirFailed)?;
+            }
+            fs::rename(&staged_path, &target_path).map_err(|e| {
+                warn!(
+                    "move staged file failed, from: {:?}, to: {:?}, err: {}",
+                    staged_path, target_path, e
+                );
+                StagingError::MoveStagedFileFailed
+            })?;
+        }
+        JournalAction::CreateDir => {
+            if fs::symlink_metadata(&target_path).is_ok() && !entry.is_existed {
+                fs::rename(&target_path, &backup_path).map_err(|_| StagingError::BackupFailed)?;
+            }
+            fs::create_dir_all(&target_path).map_err(|_| StagingError::CreateDirFailed)?;
+        }
+        JournalAction::Delete => {
+            if fs::symlink_metadata(&target_path).is_ok() {
+                fs::rename(&target_path, &backup_path).map_err(|_| StagingError::BackupFailed)?;
+            }
+        }
+        JournalAction::DeleteDir => {
+            // files in it are moved away already, the dir is kept if something is left
+            if let Err(e) = fs::remove_dir(&target_path) {
+                debug!("remove dir failed, path: {:?}, err: {}", target_path, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rollback_entry(data_path: &str, index: usize, entry: &JournalEntry) -> Result<(), StagingError> {
+    let target_path = get_path(data_path, &entry.relative_path)?;
+    let backup_path = Path::new(&get_backup_path(data_path)).join(index.to_string());
+    let is_backed_up = fs::symlink_metadata(&backup_path).is_ok();
+    match entry.action {
+        JournalAction::Write => {
+            // the new file goes back to the staging area, so it is not downloaded again
+            if (is_backed_up || !entry.is_existed) && fs::symlink_metadata(&target_path).is_ok() {
+                let staged_path = get_path(&get_staging_path(data_path), &entry.relative_path)?;
+                if let Some(parent) = staged_path.parent() {
+                    fs::create_dir_all(parent).map_err(|_| StagingError::CreateDirFailed)?;
+                }
+                if fs::rename(&target_path, &staged_path).is_err() {
+                    remove_path(&target_path).map_err(|_| StagingError::RestoreFailed)?;
+                }
+            }
+        }
+        JournalAction::CreateDir => {
+            if !entry.is_existed {
+                let _ = fs::remove_dir(&target_path);
+            }
+        }
+        JournalAction::Delete => {}
+        JournalAction::DeleteDir => {
+            fs::create_dir_all(&target_path).map_err(|_| StagingError::RestoreFailed)?;
+        }
+    }
+    if is_backed_up {
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent).map_err(|_| StagingError::CreateDirFailed)?;
+        }
+        fs::rename(&backup_path, &target_path).map_err(|e| {
+            warn!(
+                "restore failed, from: {:?}, to: {:?}, err: {}",
+                backup_path, target_path, e
+            );
+            StagingError::RestoreFailed
+        })?;
+    }
+    Ok(())
+}
+
+// the journal is read from disk, its paths are checked as those from the server
+fn get_path(base_path: &str, relative_path: &str) -> Result<PathBuf, StagingError> {
+    sync::get_contained_path(base_path, relative_path).map_err(|e| {
+        warn!(
+            "path rejected, base_path: {}, relative_path: {}, err: {:?}",
+            base_path, relative_path, e
+        );
+        StagingError::PathInvalid
+    })
+}
+
+fn clean(data_path: &str) {
+    for p in [get_staging_path(data_path), get_backup_path(data_path)] {
+        if let Err(e) = remove_path(Path::new(&p)) {
+            warn!("clean failed, path: {}, err: {}", p, e);
+        }
+    }
+}
+
+fn remove_path(p: &Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(p) {
+        Err(_) => Ok(()),
+        Ok(m) if m.is_dir() => fs::remove_dir_all(p),
+        Ok(_) => fs::remove_file(p),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::staging;
+    use crate::application::update::sync::{SyncTask, SyncTaskType};
+    use crate::test_util::TempDir;
+    use crate::types::common::{FileInfo, FileType};
+    use std::fs;
+    use std::path::Path;
+    use util::hash::HashAlgorithm;
+
+    fn task(
+        data_path: &str,
+        sync_type: SyncTaskType,
+        relative_path: &str,
+        file_type: FileType,
+    ) -> SyncTask {
+        SyncTask::new(
+            1,
+            sync_type,
+            FileInfo::new(relative_path, file_type, 0, "", HashAlgorithm::Md5),
+            data_path.to_string(),
+            staging::get_staging_path(data_path),
+        )
+    }
+
+    fn write(base_path: &str, relative_path: &str, content: &str) {
+        let p = Path::new(base_path).join(relative_path);
+        fs::create_dir_all(p.parent().unwrap()).unwrap();
+        fs::write(p, content).unwrap();
+    }
+
+    fn read(base_path: &str, relative_path: &str) -> String {
+        fs::read_to_string(Path::new(base_path).join(relative_path)).unwrap()
+    }
+
+    fn assert_cleaned(data_path: &str) {
+        assert!(!Path::new(&staging::get_journal_path(data_path)).exists());
+        assert!(!Path::new(&staging::get_backup_path(data_path)).exists());
+    }
+
+    #[test]
+    fn test_commit() {
+        let temp_dir = TempDir::new("staging");
+        let data_path = temp_dir.join("data");
+        let staging_path = staging::get_staging_path(&data_path);
+        write(&data_path, "keep.txt", "old");
+        write(&data_path, "old.txt", "old");
+        write(&staging_path, "keep.txt", "new");
+        write(&staging_path, "added/new.txt", "new");
+
+        let sync_tasks = vec![
+            task(&data_path, SyncTaskType::Update, "keep.txt", FileType::File),
+            task(&data_path, SyncTaskType::Create, "added", FileType::Dir),
+            task(
+                &data_path,
+                SyncTaskType::Create,
+                "added/new.txt",
+                FileType::File,
+            ),
+            task(&data_path, SyncTaskType::Delete, "old.txt", FileType::File),
+        ];
+        staging::commit(&data_path, &sync_tasks).unwrap();
+        assert_eq!(read(&data_path, "keep.txt"), "new");
+        assert_eq!(read(&data_path, "added/new.txt"), "new");
+        assert!(!Path::new(&data_path).join("old.txt").exists());
+        assert!(!Path::new(&staging_path).exists());
+        assert_cleaned(&data_path);
+
+        // the journal is removed on commit, there is nothing left to roll back
+        staging::rollback(&data_path).unwrap();
+        assert_eq!(read(&data_path, "keep.txt"), "new");
+        assert!(!Path::new(&data_path).join("old.txt").exists());
+    }
+
+    #[test]
+    fn test_commit_failed_rolled_back() {
+        let temp_dir = TempDir::new("staging");
+        let data_path = temp_dir.join("data");
+        let staging_path = staging::get_staging_path(&data_path);
+        write(&data_path, "keep.txt", "old");
+        write(&data_path, "old.txt", "old");
+        write(&staging_path, "keep.txt", "new");
+
+        // missing.txt is not staged, moving it fails after keep.txt is switched
+        let sync_tasks = vec![
+            task(&data_path, SyncTaskType::Update, "keep.txt", FileType::File),
+            task(
+                &data_path,
+                SyncTaskType::Create,
+                "missing.txt",
+                FileType::File,
+            ),
+            task(&data_path, SyncTaskType::Delete, "old.txt", FileType::File),
+        ];
+        assert!(staging::commit(&data_path, &sync_tasks).is_err());
+        assert_eq!(read(&data_path, "keep.txt"), "old");
+        assert_eq!(read(&data_path, "old.txt"), "old");
+        assert!(!Path::new(&data_path).join("missing.txt").exists());
+        // the new file is kept for the next try
+        assert_eq!(read(&staging_path, "keep.txt"), "new");
+        assert_cleaned(&data_path);
+    }
+
+    #[test]
+    fn test_discard() {
+        let temp_dir = TempDir::new("staging");
+        let data_path = temp_dir.join("data");
+        let staging_path = staging::get_staging_path(&data_path);
+        write(&data_path, "keep.txt", "old");
+        write(&staging_path, "keep.txt", "new");
+        // interrupted after the first entry
+        let sync_tasks = vec![task(
+            &data_path,
+            SyncTaskType::Update,
+            "keep.txt",
+            FileType::File,
+        )];
+        let journal = staging::make_journal(&data_path, &sync_tasks);
+        fs::create_dir_all(staging::get_backup_path(&data_path)).unwrap();
+        fs::write(
+            staging::get_journal_path(&data_path),
+            serde_json::to_string(&journal).unwrap(),
+        )
+        .unwrap();
+        staging::apply_entry(&data_path, 0, &journal.entries[0]).unwrap();
+
+        staging::discard(&data_path).unwrap();
+        assert_eq!(read(&data_path, "keep.txt"), "old");
+        assert!(!Path::new(&staging_path).exists());
+        assert_cleaned(&data_path);
+    }
+
+    #[test]
+    fn test_rollback_all_interrupted() {
+        let temp_dir = TempDir::new("staging");
+        let data_dir_path = temp_dir.join("data");
+        let data_path = format!("{}/app/1/1/game", data_dir_path);
+        let staging_path = staging::get_staging_path(&data_path);
+        write(&data_path, "keep.txt", "old");
+        write(&data_path, "old.txt", "old");
+        write(&staging_path, "keep.txt", "new");
+        write(&staging_path, "added.txt", "new");
+
+        let sync_tasks = vec![
+            task(&data_path, SyncTaskType::Update, "keep.txt", FileType::File),
+            task(
+                &data_path,
+                SyncTaskType::Create,
+                "added.txt",
+                FileType::File,
+            ),
+            task(&data_path, SyncTaskType::Delete, "old.txt", FileType::File),
+        ];
+        // the same steps as commit, killed after the first two entries
+        let journal = staging::make_journal(&data_path, &sync_tasks);
+        fs::create_dir_all(staging::get_backup_path(&data_path)).unwrap();
+        fs::write(
+            staging::get_journal_path(&data_path),
+            serde_json::to_string(&journal).unwrap(),
+        )
+        .unwrap();
+        for (i, entry) in journal.entries.iter().enumerate().take(2) {
+            staging::apply_entry(&data_path, i, entry).unwrap();
+        }
+        assert_eq!(read(&data_path, "keep.txt"), "new");
+
+        staging::rollback_all(&data_dir_path);
+        assert_eq!(read(&data_path, "keep.txt"), "old");
+        assert_eq!(read(&data_path, "old.txt"), "old");
+        assert!(!Path::new(&data_path).join("added.txt").exists());
+        assert_eq!(read(&staging_path, "added.txt"), "new");
+        assert_cleaned(&data_path);
+    }
+
+    #[test]
+    fn test_commit_type_changed() {
+        let temp_dir = TempDir::new("staging");
+        let data_path = temp_dir.join("data");
+        let staging_path = staging::get_staging_path(&data_path);
+        // a is a dir to become a file, b a file to become a dir
+        write(&data_path, "a/x.txt", "old");
+        write(&data_path, "b", "old");
+        write(&staging_path, "a", "new");
+        write(&staging_path, "b/y.txt", "new");
+        let sync_tasks = vec![
+            task(&data_path, SyncTaskType::Update, "a", FileType::File),
+            task(&data_path, SyncTaskType::Delete, "a/x.txt", FileType::File),
+            task(&data_path, SyncTaskType::Update, "b", FileType::Dir),
+            task(&data_path, SyncTaskType::Create, "b/y.txt", FileType::File),
+        ];
+
+        // rolled back when a later entry fails
+        let mut failed_tasks = sync_tasks.clone();
+        failed_tasks.push(task(
+            &data_path,
+            SyncTaskType::Create,
+            "missing.txt",
+            FileType::File,
+        ));
+        assert!(staging::commit(&data_path, &failed_tasks).is_err());
+        assert_eq!(read(&data_path, "a/x.txt"), "old");
+        assert_eq!(read(&data_path, "b"), "old");
+        assert_cleaned(&data_path);
+
+        staging::commit(&data_path, &sync_tasks).unwrap();
+        assert_eq!(read(&data_path, "a"), "new");
+        assert_eq!(read(&data_path, "b/y.txt"), "new");
+        assert_cleaned(&data_path);
+    }
+
+    #[test]
+    fn test_path_outside_rejected() {
+        let temp_dir = TempDir::new("staging");
+        let data_path = temp_dir.join("data");
+        let outside_path = temp_dir.join("outside.txt");
+        fs::create_dir_all(&data_path).unwrap();
+        fs::write(&outside_path, "user").unwrap();
+
+        let sync_tasks = vec![SyncTask::new(
+            1,
+            SyncTaskType::Delete,
+            FileInfo::new(
+                "a/../../outside.txt",
+                FileType::File,
+                4,
+                "",
+                HashAlgorithm::Md5,
+            ),
+            data_path.clone(),
+            staging::get_staging_path(&data_path),
+        )];
+        assert!(staging::commit(&data_path, &sync_tasks).is_err());
+        assert!(Path::new(&outside_path).exists());
+
+        // a journal left on disk is not trusted either, the file would be moved to staging
+        fs::write(
+            staging::get_journal_path(&data_path),
+            r#"{"entries":[{"relative_path":"../outside.txt","action":"Write","is_existed":false}]}"#,
+        )
+        .unwrap();
+        assert!(staging::rollback(&data_path).is_err());
+        assert_eq!(fs::read_to_string(&outside_path).unwrap(), "user");
+    }
+}
@@ -1,13 +1,25 @@
+use crate::application::update::data_node::DataNodeSelector;
+use crate::application::update::delta;
+use crate::application::update::metadata;
 use crate::cache;
+use crate::cache::CacheFile;
+use crate::config::CONFIG;
+use crate::request::DOWNLOAD_RATE_LIMITER;
 use crate::types::common::{DataNode, FileInfo, FileType};
 use log::{debug, warn};
-use rand::{thread_rng, Rng};
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
 use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use std::{fs, io};
-use util::hash;
+use util::compress;
+use util::rate_limit::RateLimitedReader;
+
+pub const PART_FILE_SUFFIX: &str = ".ship-part";
 
 #[derive(Clone, Debug)]
 pub struct SyncTask {
@@ -15,7 +27,8 @@ pub struct SyncTask {
     pub sync_type: SyncTaskType,
     pub file_info: FileInfo,
     pub base_path: String,
-    pub data_nodes: Vec<DataNode>,
+    // Create and Update write to the staging area, see staging
+    pub staging_path: String,
 }
 
 impl SyncTask {
@@ -24,28 +37,38 @@ impl SyncTask {
         sync_type: SyncTaskType,
         file_info: FileInfo,
         base_path: String,
-        data_nodes: Vec<DataNode>,
+        staging_path: String,
     ) -> Self {
         Self {
             app_id,
             sync_type,
             file_info,
             base_path,
-            data_nodes,
+            staging_path,
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum SyncTaskType {
     Create,
     Update,
     Delete,
+    // only the mode differs, it is applied when staging is committed, nothing is downloaded
+    UpdateMetadata,
 }
 
 #[derive(Debug)]
 pub enum SyncError {
     PathInvalid,
+    // the relative path has a .. component
+    PathTraversal,
+    // the relative path has a root or a drive prefix
+    PathAbsolute,
+    // a parent of the path is an existing symlink, the file would be written elsewhere
+    PathThroughSymlink,
+    // the symlink would point outside of the data dir
+    SymlinkTargetOutside,
     DownloadFailed,
     CreateFileFailed,
     ReadDownloadContentFailed,
@@ -54,31 +77,73 @@ pub enum SyncError {
     SyncFromCacheError,
     CreateDirFailed,
     CreateSymlinkFailed,
+    RenamePartFileFailed,
+    RangeNotSupported,
+    NoDataNodeAvailable,
+    DeltaNotAvailable,
+    ReadLocalFileFailed,
 
     HashSumSyncedFileError,
     SyncedFileHashSumNotMatch,
     DeleteFailed,
+    ApplyMetadataFailed,
     UnknownFileType,
     CheckExistsFailed,
 
     Cancel,
 }
 
-pub fn handle_task(task: SyncTask, is_cancel: Arc<AtomicBool>) -> Result<(), SyncError> {
+// bytes of a SyncTask counted into the progress of the whole update, never more than the size
+// of the file, so that bytes downloaded again by a retry are not counted twice
+pub struct TaskProgress {
+    done_bytes: Arc<AtomicU64>,
+    size: u64,
+    counted: Cell<u64>,
+}
+
+impl TaskProgress {
+    pub fn new(done_bytes: Arc<AtomicU64>, size: u64) -> Self {
+        Self {
+            done_bytes,
+            size,
+            counted: Cell::new(0),
+        }
+    }
+
+    pub fn add(&self, n: u64) {
+        let counted = self.counted.get();
+        let new_counted = (counted + n).min(self.size);
+        self.done_bytes
+            .fetch_add(new_counted - counted, Ordering::Relaxed);
+        self.counted.set(new_counted);
+    }
+
+    // the rest of the file, e.g. copied from the cache or staged already
+    pub fn finish(&self) {
+        self.add(self.size);
+    }
+}
+
+pub fn handle_task(
+    task: SyncTask,
+    data_node_selector: Arc<DataNodeSelector>,
+    progress: &TaskProgress,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<(), SyncError> {
     debug!("SyncTask: {:?}", task);
 
     match task.sync_type {
         SyncTaskType::Create | SyncTaskType::Update => {
             debug!("will sync, file_info: {:?}", task.file_info);
 
-            let full_file_path = Path::new(&task.base_path).join(&task.file_info.relative_path);
-            if !full_file_path.starts_with(&task.base_path) {
-                return Err(SyncError::PathInvalid);
-            }
-            delete_file(&full_file_path)?;
+            // the file in data dir is only read here, it is replaced when staging is committed
+            let full_file_path =
+                get_contained_path(&task.base_path, &task.file_info.relative_path)?;
+            let staged_file_path =
+                get_contained_path(&task.staging_path, &task.file_info.relative_path)?;
 
             // create parent dir
-            if let Some(parent_dir) = full_file_path.parent() {
+            if let Some(parent_dir) = staged_file_path.parent() {
                 fs::create_dir_all(parent_dir).map_err(|_| SyncError::CreateDirFailed)?;
             }
 
@@ -87,101 +152,87 @@ pub fn handle_task(task: SyncTask, is_cancel: Arc<AtomicBool>) -> Result<(), Syn
                     return Err(SyncError::UnknownFileType);
                 }
                 FileType::File => {
-                    let cache_file_o = cache::get_cache_file(&task.file_info.hash);
-                    match cache_file_o {
-                        None => {
-                            let index = thread_rng().gen_range(0..task.data_nodes.len());
-                            let url = format!(
-                                "{}/api/v1/download?file={}",
-                                task.data_nodes
-                                    .get(index)
-                                    .unwrap()
-                                    .address
-                                    .to_address_string(),
-                                task.file_info.relative_path
-                            );
-                            let mut resp = reqwest::blocking::get(url)
-                                .map_err(|_| SyncError::DownloadFailed)?;
-
-                            let f = fs::File::create(&full_file_path)
-                                .map_err(|_| SyncError::CreateFileFailed)?;
-                            let mut cap: usize = 8 * 1024;
-                            let file_size = task.file_info.size;
-                            if file_size < 1024 * 1024 {
-                                cap = file_size as usize;
-                            }
-                            let mut writer = io::BufWriter::with_capacity(cap, f);
-                            let mut buf = [0; 1024 * 1024];
-                            loop {
-                                // control
-                                if is_cancel.load(Ordering::Relaxed) {
-                                    return Err(SyncError::Cancel);
-                                }
-
-                                let n = resp
-                                    .read(&mut buf)
-                                    .map_err(|_| SyncError::ReadDownloadContentFailed)?;
-                                if n == 0 {
-                                    break;
-                                }
-                                writer
-                                    .write(&buf[..n])
-                                    .map_err(|_| SyncError::WriteDownloadContentFailed)?;
-                            }
-                            writer.flush().map_err(|_| SyncError::CreateFileFailed)?;
-                            check_hash(&task, &full_file_path)?;
-                            cache::add_to_cache(&full_file_path, task.app_id)
-                                .map_err(|_| SyncError::AddToCacheFailed)?;
-                        }
-                        Some(cache_file) => {
-                            let cache_dir = cache::get_update_cache_dir_path()
-                                .map_err(|_| SyncError::SyncFromCacheError)?;
-                            let cache_file_path =
-                                Path::new(&cache_dir).join(cache_file.relative_path);
-                            fs::copy(cache_file_path, &full_file_path)
-                                .map_err(|_| SyncError::SyncFromCacheError)?;
-                            check_hash(&task, &full_file_path)?;
+                    // staged by an update that failed or was canceled
+                    if is_staged(&task, &staged_file_path) {
+                        debug!("file already staged, path: {:?}", staged_file_path);
+                        return apply_metadata(&task, &staged_file_path);
+                    }
+                    let cache_file_o =
+                        cache::get_cache_file(task.file_info.hash_algorithm, &task.file_info.hash);
+                    let is_synced_from_cache = match cache_file_o {
+                        None => false,
+                        Some(cache_file) => sync_from_cache(&task, &cache_file, &staged_file_path)?,
+                    };
+                    if !is_synced_from_cache {
+                        let part_file_path = get_part_file_path(&staged_file_path);
+                        let is_delta_synced = try_delta_sync(
+                            &task,
+                            &data_node_selector,
+                            &full_file_path,
+                            &part_file_path,
+                            progress,
+                            Arc::clone(&is_cancel),
+                        )?;
+                        if !is_delta_synced {
+                            download_file(
+                                &task,
+                                &data_node_selector,
+                                &part_file_path,
+                                progress,
+                                Arc::clone(&is_cancel),
+                            )?;
                         }
+                        delete_file(&staged_file_path)?;
+                        fs::rename(&part_file_path, &staged_file_path).map_err(|e| {
+                            warn!(
+                                "rename part file failed, part_file_path: {:?}, err: {}",
+                                part_file_path, e
+                            );
+                            SyncError::RenamePartFileFailed
+                        })?;
+                        cache::add_to_cache(
+                            &staged_file_path,
+                            task.app_id,
+                            task.file_info.hash_algorithm,
+                            !metadata::is_set(&task.file_info),
+                        )
+                        .map_err(|_| SyncError::AddToCacheFailed)?;
                     }
+                    apply_metadata(&task, &staged_file_path)?;
                 }
                 FileType::Dir => {
-                    fs::create_dir_all(&full_file_path).map_err(|e| {
-                        warn!(
-                            "create dir failed, full_file_path: {:?}, err: {}",
-                            full_file_path, e
-                        );
-                        SyncError::CreateDirFailed
-                    })?;
+                    // created when staging is committed
                 }
                 FileType::Symlink => {
-                    let mut content = "".to_string();
-                    let index = thread_rng().gen_range(0..task.data_nodes.len());
-                    let url = format!(
-                        "{}/api/v1/download?file={}",
-                        task.data_nodes
-                            .get(index)
-                            .unwrap()
-                            .address
-                            .to_address_string(),
-                        task.file_info.relative_path
-                    );
-                    let mut resp =
-                        reqwest::blocking::get(url).map_err(|_| SyncError::DownloadFailed)?;
-                    resp.read_to_string(&mut content)
-                        .map_err(|_| SyncError::ReadDownloadContentFailed)?;
-                    let original_path = Path::new(&content);
+                    delete_file(&staged_file_path)?;
+                    let content = with_data_node(&data_node_selector, |index, data_node| {
+                        let mut content = "".to_string();
+                        let url = get_download_url(data_node, &task);
+                        let start_time = Instant::now();
+                        let mut resp =
+                            reqwest::blocking::get(url).map_err(|_| SyncError::DownloadFailed)?;
+                        data_node_selector.report_latency(index, start_time.elapsed());
+                        if !resp.status().is_success() {
+                            return Err(SyncError::DownloadFailed);
+                        }
+                        resp.read_to_string(&mut content)
+                            .map_err(|_| SyncError::ReadDownloadContentFailed)?;
+                        Ok(content)
+                    })?;
+                    let original_path =
+                        get_symlink_target_path(&task.base_path, &full_file_path, &content)?;
                     let create_symlink_r;
                     if original_path.is_dir() {
                         create_symlink_r =
-                            util::fs::symlink::symlink_dir(&content, &full_file_path);
+                            util::fs::symlink::symlink_dir(&content, &staged_file_path);
                     } else {
                         create_symlink_r =
-                            util::fs::symlink::symlink_file(&content, &full_file_path);
+                            util::fs::symlink::symlink_file(&content, &staged_file_path);
                     }
                     if let Err(e) = create_symlink_r {
                         warn!(
-                            "create symlink failed, full_file_path: {:?}, err: {}",
-                            full_file_path, e
+                            "create symlink failed, staged_file_path: {:?}, err: {}",
+                            staged_file_path, e
                         );
                         return Err(SyncError::CreateSymlinkFailed);
                     }
@@ -189,22 +240,443 @@ pub fn handle_task(task: SyncTask, is_cancel: Arc<AtomicBool>) -> Result<(), Syn
             }
         }
         SyncTaskType::Delete => {
+            // deleted when staging is committed, so the data dir is untouched if update failed
             debug!("will delete, file_info: {:?}", task.file_info);
-            let full_file_path = Path::new(&task.base_path).join(&task.file_info.relative_path);
+            get_contained_path(&task.base_path, &task.file_info.relative_path)?;
+        }
+        SyncTaskType::UpdateMetadata => {
+            debug!("will update metadata, file_info: {:?}", task.file_info);
+            get_contained_path(&task.base_path, &task.file_info.relative_path)?;
+        }
+    }
+    Ok(())
+}
+
+// relative paths come from the server, both / and \\ are separators as in diff::normalize_path,
+// the path is joined onto base_path only if it can not get out of it
+pub fn get_contained_path(base_path: &str, relative_path: &str) -> Result<PathBuf, SyncError> {
+    if relative_path.starts_with(['/', '\\']) || has_drive_prefix(relative_path) {
+        warn!("absolute path rejected, relative_path: {}", relative_path);
+        return Err(SyncError::PathAbsolute);
+    }
+    let components = relative_path
+        .split(['/', '\\'])
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect::<Vec<&str>>();
+    if components.is_empty() {
+        return Err(SyncError::PathInvalid);
+    }
+    if components.contains(&"..") {
+        warn!("path traversal rejected, relative_path: {}", relative_path);
+        return Err(SyncError::PathTraversal);
+    }
+    let mut p = PathBuf::from(base_path);
+    for (i, x) in components.iter().enumerate() {
+        // a prefix in a later component on Windows
+        if !matches!(
+            Path::new(x).components().collect::<Vec<Component>>()[..],
+            [Component::Normal(_)]
+        ) {
+            return Err(SyncError::PathAbsolute);
+        }
+        p.push(x);
+        // the last component may be a symlink itself, it is replaced or deleted but not followed
+        let is_parent = i + 1 < components.len();
+        if is_parent && p.is_symlink() {
+            warn!(
+                "path through symlink rejected, relative_path: {}, symlink: {:?}",
+                relative_path, p
+            );
+            return Err(SyncError::PathThroughSymlink);
+        }
+    }
+    Ok(p)
+}
+
+// e.g. C: or C:\\, rejected on every platform since the server may be on Windows
+fn has_drive_prefix(relative_path: &str) -> bool {
+    let b = relative_path.as_bytes();
+    b.len() >= 2 && b[0].is_ascii_alphabetic() && b[1] == b':'
+}
+
+// the target is resolved against the dir of the link in the data dir, where the link is moved to
+// when staging is committed, and checked lexically, it may not exist yet
+pub fn get_symlink_target_path(
+    base_path: &str,
+    full_file_path: &Path,
+    target: &str,
+) -> Result<PathBuf, SyncError> {
+    let target_path = Path::new(target);
+    let joined = if target_path.is_absolute() || has_drive_prefix(target) {
+        target_path.to_path_buf()
+    } else {
+        full_file_path
+            .parent()
+            .unwrap_or(Path::new(base_path))
+            .join(target_path)
+    };
+    let mut resolved = PathBuf::new();
+    for x in joined.components() {
+        match x {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return Err(SyncError::SymlinkTargetOutside);
+                }
+            }
+            _ => resolved.push(x),
+        }
+    }
+    if !resolved.starts_with(base_path) {
+        warn!(
+            "symlink target rejected, path: {:?}, target: {}",
+            full_file_path, target
+        );
+        return Err(SyncError::SymlinkTargetOutside);
+    }
+    Ok(resolved)
+}
+
+pub fn get_download_url(data_node: &DataNode, task: &SyncTask) -> String {
+    format!(
+        "{}/api/v1/download?file={}",
+        data_node.address.to_address_string(),
+        task.file_info.relative_path
+    )
+}
+
+// try data nodes one by one until one of them works, failures are recorded to the selector
+pub fn with_data_node<T, F>(data_node_selector: &DataNodeSelector, mut f: F) -> Result<T, SyncError>
+where
+    F: FnMut(usize, &DataNode) -> Result<T, SyncError>,
+{
+    let mut tried = vec![];
+    let mut last_err = SyncError::NoDataNodeAvailable;
+    while let Some((index, data_node)) = data_node_selector.select(&tried) {
+        tried.push(index);
+        match f(index, &data_node) {
+            Ok(x) => {
+                data_node_selector.report_success(index);
+                return Ok(x);
+            }
+            Err(e) => match e {
+                SyncError::DownloadFailed | SyncError::ReadDownloadContentFailed => {
+                    warn!(
+                        "download from data node failed, try next, data_node: {:?}, err: {:?}",
+                        data_node, e
+                    );
+                    data_node_selector.report_failure(index);
+                    last_err = e;
+                }
+                e => {
+                    return Err(e);
+                }
+            },
+        }
+    }
+    Err(last_err)
+}
+
+// partial download is kept next to the target file, resumed by a Range request next time
+pub fn get_part_file_path(full_file_path: &Path) -> PathBuf {
+    let mut file_name = full_file_path
+        .file_name()
+        .map(|x| x.to_os_string())
+        .unwrap_or_default();
+    file_name.push(PART_FILE_SUFFIX);
+    full_file_path.with_file_name(file_name)
+}
+
+// only for changed big files, whole file download is used when false is returned
+fn try_delta_sync(
+    task: &SyncTask,
+    data_node_selector: &DataNodeSelector,
+    full_file_path: &PathBuf,
+    part_file_path: &PathBuf,
+    progress: &TaskProgress,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<bool, SyncError> {
+    if !CONFIG.update.delta_sync
+        || !matches!(task.sync_type, SyncTaskType::Update)
+        || task.file_info.size < delta::DELTA_SYNC_MIN_FILE_SIZE
+        || !full_file_path.is_file()
+        || full_file_path.is_symlink()
+    {
+        return Ok(false);
+    }
+    // resuming an interrupted download is cheaper
+    if get_part_file_size(part_file_path) > 0 {
+        return Ok(false);
+    }
+
+    let r = delta::delta_sync(
+        task,
+        data_node_selector,
+        full_file_path,
+        part_file_path,
+        progress,
+        is_cancel,
+    )
+    .and_then(|_| check_hash(task, part_file_path));
+    match r {
+        Ok(_) => Ok(true),
+        Err(SyncError::Cancel) => Err(SyncError::Cancel),
+        Err(e) => {
+            warn!(
+                "delta sync failed, fall back to whole file, err: {:?}, file_info: {:?}",
+                e, task.file_info
+            );
+            delete_file(part_file_path)?;
+            Ok(false)
+        }
+    }
+}
+
+fn is_staged(task: &SyncTask, staged_file_path: &PathBuf) -> bool {
+    match fs::symlink_metadata(staged_file_path) {
+        Ok(m) if m.is_file() && m.len() == task.file_info.size => {
+            check_hash(task, staged_file_path).is_ok()
+        }
+        _ => false,
+    }
+}
+
+fn get_part_file_size(part_file_path: &PathBuf) -> u64 {
+    match fs::metadata(part_file_path) {
+        Ok(m) if m.is_file() => m.len(),
+        _ => 0,
+    }
+}
+
+fn download_file(
+    task: &SyncTask,
+    data_node_selector: &DataNodeSelector,
+    part_file_path: &PathBuf,
+    progress: &TaskProgress,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<(), SyncError> {
+    let file_size = task.file_info.size;
+    let offset = get_part_file_size(part_file_path);
+    if offset > 0 {
+        if offset == file_size && check_hash(task, part_file_path).is_ok() {
+            debug!("part file already completed, path: {:?}", part_file_path);
+            return Ok(());
+        }
+        if offset >= file_size {
+            // stale or corrupted, start over
+            delete_file(part_file_path)?;
+        } else {
             debug!(
-                "will delete, file_info: {:?}, full_file_path: {:?}",
-                task.file_info, full_file_path
+                "resume download, offset: {}, file_info: {:?}",
+                offset, task.file_info
+            );
+            let r = do_download_file(
+                task,
+                data_node_selector,
+                part_file_path,
+                progress,
+                Arc::clone(&is_cancel),
+            )
+            .and_then(|_| check_hash(task, part_file_path));
+            match r {
+                Ok(_) => return Ok(()),
+                Err(SyncError::Cancel) => return Err(SyncError::Cancel),
+                Err(e) => {
+                    warn!(
+                        "resume download failed, retry from start, err: {:?}, file_info: {:?}",
+                        e, task.file_info
+                    );
+                    delete_file(part_file_path)?;
+                }
+            }
+        }
+    }
+
+    let r = do_download_file(
+        task,
+        data_node_selector,
+        part_file_path,
+        progress,
+        is_cancel,
+    )
+    .and_then(|_| check_hash(task, part_file_path));
+    if let Err(SyncError::SyncedFileHashSumNotMatch) = r {
+        // don't resume from a broken file next time
+        delete_file(part_file_path)?;
+    }
+    r.map(|_| ())
+}
+
+fn do_download_file(
+    task: &SyncTask,
+    data_node_selector: &DataNodeSelector,
+    part_file_path: &PathBuf,
+    progress: &TaskProgress,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<(), SyncError> {
+    with_data_node(data_node_selector, |index, data_node| {
+        download_from_data_node(
+            task,
+            data_node_selector,
+            index,
+            data_node,
+            part_file_path,
+            progress,
+            Arc::clone(&is_cancel),
+        )
+    })
+}
+
+fn download_from_data_node(
+    task: &SyncTask,
+    data_node_selector: &DataNodeSelector,
+    index: usize,
+    data_node: &DataNode,
+    part_file_path: &PathBuf,
+    progress: &TaskProgress,
+    is_cancel: Arc<AtomicBool>,
+) -> Result<(), SyncError> {
+    // continue from the part file, it may be left by last update or by a failed data node
+    let offset = get_part_file_size(part_file_path);
+    let url = get_download_url(data_node, task);
+    let mut req = reqwest::blocking::Client::new().get(url);
+    // offsets of a compressed body are not offsets of the file, so a resumed download is never
+    // compressed, the part file always holds decompressed bytes
+    let is_compression_accepted = offset == 0 && CONFIG.update.compressed_download;
+    if offset > 0 {
+        req = req.header(header::RANGE, format!("bytes={}-", offset));
+    }
+    if is_compression_accepted {
+        req = req.header(header::ACCEPT_ENCODING, compress::ACCEPT_ENCODING);
+    }
+    let start_time = Instant::now();
+    let resp = req.send().map_err(|_| SyncError::DownloadFailed)?;
+    data_node_selector.report_latency(index, start_time.elapsed());
+
+    let status = resp.status();
+    let f = match get_part_file_mode(offset, status, get_content_range_start(&resp)) {
+        Ok(PartFileMode::Append) => {
+            progress.add(offset);
+            fs::OpenOptions::new()
+                .append(true)
+                .open(part_file_path)
+                .map_err(|_| SyncError::CreateFileFailed)?
+        }
+        Ok(PartFileMode::Truncate) => {
+            fs::File::create(part_file_path).map_err(|_| SyncError::CreateFileFailed)?
+        }
+        Err(e) => {
+            warn!(
+                "download failed, status: {}, url: {}, headers: {:?}",
+                status,
+                resp.url(),
+                resp.headers()
             );
-            delete_file(&full_file_path)?;
+            return Err(e);
+        }
+    };
+
+    let mut cap: usize = 8 * 1024;
+    let file_size = task.file_info.size;
+    if file_size < 1024 * 1024 {
+        cap = file_size as usize;
+    }
+    let mut writer = io::BufWriter::with_capacity(cap, f);
+    let content_encoding = if is_compression_accepted {
+        resp.headers()
+            .get(header::CONTENT_ENCODING)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_string())
+    } else {
+        None
+    };
+    // the limit is for the bytes on the wire, the hash is checked on the decompressed file
+    let mut reader = compress::decode_reader(
+        content_encoding.as_deref(),
+        RateLimitedReader::new(resp, &DOWNLOAD_RATE_LIMITER),
+    )
+    .map_err(|e| {
+        warn!("decode download content failed, err: {}", e);
+        SyncError::DownloadFailed
+    })?;
+    let mut buf = [0; 1024 * 1024];
+    loop {
+        // control
+        if is_cancel.load(Ordering::Relaxed) {
+            // keep what we have got, it will be resumed next time
+            writer.flush().map_err(|_| SyncError::CreateFileFailed)?;
+            return Err(SyncError::Cancel);
         }
+
+        let n = reader
+            .read(&mut buf)
+            .map_err(|_| SyncError::ReadDownloadContentFailed)?;
+        if n == 0 {
+            break;
+        }
+        writer
+            .write_all(&buf[..n])
+            .map_err(|_| SyncError::WriteDownloadContentFailed)?;
+        progress.add(n as u64);
     }
+    writer.flush().map_err(|_| SyncError::CreateFileFailed)?;
     Ok(())
 }
 
+#[derive(Debug, PartialEq)]
+enum PartFileMode {
+    // the body continues the part file
+    Append,
+    // the body is the whole file
+    Truncate,
+}
+
+// how the response to a download from offset is written to the part file
+fn get_part_file_mode(
+    offset: u64,
+    status: StatusCode,
+    content_range_start: Option<u64>,
+) -> Result<PartFileMode, SyncError> {
+    if offset > 0 && status == StatusCode::PARTIAL_CONTENT {
+        if content_range_start != Some(offset) {
+            warn!(
+                "unexpected Content-Range, offset: {}, start: {:?}",
+                offset, content_range_start
+            );
+            return Err(SyncError::RangeNotSupported);
+        }
+        Ok(PartFileMode::Append)
+    } else if status.is_success() {
+        if offset > 0 {
+            // the data node ignored the Range header and sent the whole file
+            debug!("range not supported by data node, download from start");
+        }
+        Ok(PartFileMode::Truncate)
+    } else if status == StatusCode::RANGE_NOT_SATISFIABLE {
+        Err(SyncError::RangeNotSupported)
+    } else {
+        Err(SyncError::DownloadFailed)
+    }
+}
+
+pub fn get_content_range_start(resp: &reqwest::blocking::Response) -> Option<u64> {
+    parse_content_range_start(resp.headers().get(header::CONTENT_RANGE)?.to_str().ok()?)
+}
+
+// Content-Range: bytes <start>-<end>/<size>
+fn parse_content_range_start(v: &str) -> Option<u64> {
+    let range = v.trim().strip_prefix("bytes ")?;
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse::<u64>().ok()
+}
+
 fn check_hash(task: &SyncTask, full_file_path: &PathBuf) -> Result<bool, SyncError> {
     // check hash
-    let hash_sum =
-        hash::md5::md5_file(&full_file_path).map_err(|_| SyncError::HashSumSyncedFileError)?;
+    let hash_sum = task
+        .file_info
+        .hash_algorithm
+        .hash_file(full_file_path)
+        .map_err(|_| SyncError::HashSumSyncedFileError)?;
     if task.file_info.hash != hash_sum {
         warn!(
             "synced file hash != file info hash, hash: {}, file_info: {:?}",
@@ -220,6 +692,52 @@ fn check_hash(task: &SyncTask, full_file_path: &PathBuf) -> Result<bool, SyncErr
     Ok(true)
 }
 
+// the cache file is linked or copied, see cache::link_or_copy. A hard linked file modified in
+// place in a data dir changes the cache file too, it is evicted then and false is returned, so
+// that the file is downloaded.
+fn sync_from_cache(
+    task: &SyncTask,
+    cache_file: &CacheFile,
+    staged_file_path: &PathBuf,
+) -> Result<bool, SyncError> {
+    delete_file(staged_file_path)?;
+    let cache_dir =
+        cache::get_update_cache_dir_path().map_err(|_| SyncError::SyncFromCacheError)?;
+    let cache_file_path = Path::new(&cache_dir).join(&cache_file.relative_path);
+    let is_hard_link_allowed = !metadata::is_set(&task.file_info);
+    cache::link_or_copy(&cache_file_path, staged_file_path, is_hard_link_allowed).map_err(|e| {
+        warn!(
+            "sync from cache failed, cache_file_path: {:?}, err: {}",
+            cache_file_path, e
+        );
+        SyncError::SyncFromCacheError
+    })?;
+    match check_hash(task, staged_file_path) {
+        Ok(_) => Ok(true),
+        Err(SyncError::SyncedFileHashSumNotMatch) => {
+            warn!("cache file changed, evict it, path: {:?}", cache_file_path);
+            delete_file(staged_file_path)?;
+            if let Err(e) =
+                cache::remove_cache_file(task.file_info.hash_algorithm, &task.file_info.hash)
+            {
+                warn!("evict cache file failed, err: {:?}", e);
+            }
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn apply_metadata(task: &SyncTask, staged_file_path: &Path) -> Result<(), SyncError> {
+    metadata::apply(&task.file_info, staged_file_path).map_err(|e| {
+        warn!(
+            "apply metadata failed, path: {:?}, err: {}",
+            staged_file_path, e
+        );
+        SyncError::ApplyMetadataFailed
+    })
+}
+
 fn delete_file(full_file_path: &PathBuf) -> Result<(), SyncError> {
     let full_file_path_exists_r = full_file_path.try_exists();
     match full_file_path_exists_r {
@@ -263,3 +781,127 @@ fn delete_file(full_file_path: &PathBuf) -> Result<(), SyncError> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::sync::{
+        get_contained_path, get_part_file_mode, get_symlink_target_path, parse_content_range_start,
+        PartFileMode, SyncError,
+    };
+    use crate::test_util::TempDir;
+    use reqwest::StatusCode;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_content_range_start() {
+        assert_eq!(parse_content_range_start("bytes 100-199/200"), Some(100));
+        assert_eq!(parse_content_range_start(" bytes 0-0/*"), Some(0));
+        assert_eq!(parse_content_range_start("bytes */200"), None);
+        assert_eq!(parse_content_range_start("items 100-199/200"), None);
+        assert_eq!(parse_content_range_start("bytes x-199/200"), None);
+    }
+
+    #[test]
+    fn test_get_part_file_mode() {
+        assert_eq!(
+            get_part_file_mode(100, StatusCode::PARTIAL_CONTENT, Some(100)).unwrap(),
+            PartFileMode::Append
+        );
+        // the Range header is ignored, the whole file is sent
+        assert_eq!(
+            get_part_file_mode(100, StatusCode::OK, None).unwrap(),
+            PartFileMode::Truncate
+        );
+        assert_eq!(
+            get_part_file_mode(0, StatusCode::OK, None).unwrap(),
+            PartFileMode::Truncate
+        );
+        assert!(matches!(
+            get_part_file_mode(100, StatusCode::PARTIAL_CONTENT, Some(0)),
+            Err(SyncError::RangeNotSupported)
+        ));
+        assert!(matches!(
+            get_part_file_mode(100, StatusCode::RANGE_NOT_SATISFIABLE, None),
+            Err(SyncError::RangeNotSupported)
+        ));
+        assert!(matches!(
+            get_part_file_mode(0, StatusCode::NOT_FOUND, None),
+            Err(SyncError::DownloadFailed)
+        ));
+    }
+
+    #[test]
+    fn test_get_contained_path() {
+        let base_path = "/data/app";
+        assert_eq!(
+            get_contained_path(base_path, "a/./b\\c.pak").unwrap(),
+            Path::new("/data/app/a/b/c.pak")
+        );
+        assert!(matches!(
+            get_contained_path(base_path, "./"),
+            Err(SyncError::PathInvalid)
+        ));
+    }
+
+    #[test]
+    fn test_get_contained_path_traversal() {
+        for x in ["../app2/a.pak", "a/../../b", "a\\..\\..\\b"] {
+            assert!(matches!(
+                get_contained_path("/data/app", x),
+                Err(SyncError::PathTraversal)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_get_contained_path_absolute() {
+        for x in [
+            "/etc/passwd",
+            "\\\\server\\share\\a",
+            "C:\\Windows\\a.dll",
+            "c:a.dll",
+        ] {
+            assert!(matches!(
+                get_contained_path("/data/app", x),
+                Err(SyncError::PathAbsolute)
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_contained_path_through_symlink() {
+        let temp_dir = TempDir::new("sync");
+        let base_path = temp_dir.join("data");
+        let outside_path = temp_dir.join("outside");
+        std::fs::create_dir_all(&base_path).unwrap();
+        std::os::unix::fs::symlink(&outside_path, Path::new(&base_path).join("link")).unwrap();
+
+        assert!(matches!(
+            get_contained_path(&base_path, "link/a.pak"),
+            Err(SyncError::PathThroughSymlink)
+        ));
+        // the link itself can be replaced or deleted
+        assert!(get_contained_path(&base_path, "link").is_ok());
+    }
+
+    #[test]
+    fn test_get_symlink_target_path() {
+        let base_path = "/data/app";
+        let full_file_path = Path::new("/data/app/bin/link");
+        assert_eq!(
+            get_symlink_target_path(base_path, full_file_path, "../lib/a.so").unwrap(),
+            Path::new("/data/app/lib/a.so")
+        );
+        assert_eq!(
+            get_symlink_target_path(base_path, full_file_path, "/data/app/lib").unwrap(),
+            Path::new("/data/app/lib")
+        );
+        for x in ["../../app2/a.so", "/etc/passwd", "../../../../../../a"] {
+            assert!(matches!(
+                get_symlink_target_path(base_path, full_file_path, x),
+                Err(SyncError::SymlinkTargetOutside)
+            ));
+        }
+    }
+}
@@ -1,23 +1,39 @@
 use crate::application::app::AppManager;
 use crate::application::common::get_data_path_by_app_server_id;
+use crate::application::scan::{index, ScanCounter};
 use crate::application::settings::SettingsManager;
-use crate::application::update::sync::{SyncError, SyncTask, SyncTaskType};
+use crate::application::update::data_node::DataNodeSelector;
+use crate::application::update::diff;
+use crate::application::update::diff::CasePolicy;
+use crate::application::update::history::{UpdateRecord, UpdateResult};
+use crate::application::update::keep::KeepRules;
+use crate::application::update::pause::PausedUpdate;
+use crate::application::update::signature::SignatureError;
+use crate::application::update::sync::{SyncError, SyncTask, SyncTaskType, TaskProgress};
 use crate::application::update::update_manage::UpdateManager;
 use crate::application::update::{
-    Error, Progress, TaskControlMessage, UpdateTaskControlMessage, UpdateTaskStatus,
-    UpdateTaskTraceMessage,
+    check, history, metadata, pause, signature, space, staging, Error, Progress,
+    TaskControlMessage, TaskPriority, UpdateMode, UpdatePlan, UpdateTaskControlMessage,
+    UpdateTaskStatus, UpdateTaskTraceMessage, VerifyReport,
 };
 use crate::application::{scan, update};
+use crate::cache;
+use crate::config::CONFIG;
 use crate::request;
-use crate::types::common::{ClientFileInfo, DataNode, FileInfo, ServerFileInfo};
+use crate::types::common::{
+    ClientFileInfo, DataNode, FileInfo, FileType, ScanStatus, ServerFileInfo,
+};
 use log::{debug, info, warn};
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{mpsc, Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fs, thread};
 
+// the file list is fetched again at this interval while the server is scanning
+const SERVER_SCAN_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
 pub fn handle_update_control(
     message: UpdateTaskControlMessage,
     update_manager: Arc<Mutex<UpdateManager>>,
@@ -30,57 +46,207 @@ pub fn handle_update_control(
     );
 
     match message {
-        UpdateTaskControlMessage::Start { app_server_id } => {
-            // remove last UpdateTask by app_server_id, if exist
-            let mut update_manager_g = update_manager.lock().unwrap();
-            let update_task_o = update_manager_g.get_update_task_by_app_server_id(app_server_id);
-            match update_task_o {
-                None => {}
-                Some(update_task) => {
-                    let id = update_task.id;
-                    update_manager_g.update_tasks.remove(&id);
-                }
-            }
+        UpdateTaskControlMessage::Start {
+            app_server_id,
+            priority,
+        } => {
+            start_task(
+                app_server_id,
+                UpdateMode::Update,
+                priority,
+                update_manager,
+                app_manager,
+                settings_manager,
+            );
+        }
+        UpdateTaskControlMessage::Preview { app_server_id } => {
+            start_task(
+                app_server_id,
+                UpdateMode::Preview,
+                TaskPriority::Normal,
+                update_manager,
+                app_manager,
+                settings_manager,
+            );
+        }
+        UpdateTaskControlMessage::Verify { app_server_id } => {
+            start_task(
+                app_server_id,
+                UpdateMode::Verify,
+                TaskPriority::Normal,
+                update_manager,
+                app_manager,
+                settings_manager,
+            );
+        }
+        UpdateTaskControlMessage::Repair { app_server_id } => {
+            start_task(
+                app_server_id,
+                UpdateMode::Repair,
+                TaskPriority::Normal,
+                update_manager,
+                app_manager,
+                settings_manager,
+            );
+        }
+        UpdateTaskControlMessage::Resume { app_server_id } => {
+            start_task(
+                app_server_id,
+                UpdateMode::Resume,
+                TaskPriority::Normal,
+                update_manager,
+                app_manager,
+                settings_manager,
+            );
+        }
+        UpdateTaskControlMessage::Discard { app_server_id } => {
+            discard_task(app_server_id, update_manager, app_manager, settings_manager);
+        }
+        UpdateTaskControlMessage::Pause { app_server_id } => {
+            send_task_control_message(app_server_id, TaskControlMessage::Pause, update_manager);
+        }
+        UpdateTaskControlMessage::Stop { app_server_id } => {
+            send_task_control_message(app_server_id, TaskControlMessage::Stop, update_manager);
+        }
+        UpdateTaskControlMessage::Confirm { app_server_id } => {
+            send_task_control_message(app_server_id, TaskControlMessage::Confirm, update_manager);
+        }
+    }
+}
+
+fn start_task(
+    app_server_id: u64,
+    mode: UpdateMode,
+    priority: TaskPriority,
+    update_manager: Arc<Mutex<UpdateManager>>,
+    app_manager: Arc<Mutex<AppManager>>,
+    settings_manager: Arc<Mutex<SettingsManager>>,
+) {
+    // remove last UpdateTask by app_server_id, if exist and not queued or running
+    let mut update_manager_g = update_manager.lock().unwrap();
+    if update_manager_g
+        .is_update_processing(app_server_id)
+        .unwrap_or(false)
+    {
+        drop(update_manager_g);
+        warn!(
+            "update task is queued or running already, app_server_id: {}",
+            app_server_id
+        );
+        return;
+    }
+    let update_task_o = update_manager_g.get_update_task_by_app_server_id(app_server_id);
+    match update_task_o {
+        None => {}
+        Some(update_task) => {
+            let id = update_task.id;
+            let _ = update_manager_g.remove_task(id);
+        }
+    }
+    drop(update_manager_g);
+
+    if let Err(e) = handle_task(
+        app_server_id,
+        mode,
+        priority,
+        update_manager,
+        app_manager,
+        settings_manager,
+    ) {
+        warn!("handle update_task failed, err: {:?}", e);
+    }
+}
+
+// the paused task is removed, so the app server is shown as not updated, its pause file and
+// staging area are deleted. Journals of other data paths may belong to running updates, only
+// this one is rolled back.
+fn discard_task(
+    app_server_id: u64,
+    update_manager: Arc<Mutex<UpdateManager>>,
+    app_manager: Arc<Mutex<AppManager>>,
+    settings_manager: Arc<Mutex<SettingsManager>>,
+) {
+    let mut update_manager_g = update_manager.lock().unwrap();
+    let id = match update_manager_g.get_update_task_by_app_server_id(app_server_id) {
+        Some(x) if matches!(x.status, UpdateTaskStatus::Paused) => x.id,
+        _ => {
             drop(update_manager_g);
+            warn!(
+                "no paused update to discard, app_server_id: {}",
+                app_server_id
+            );
+            return;
+        }
+    };
+    let _ = update_manager_g.remove_task(id);
+    drop(update_manager_g);
 
-            if let Err(e) =
-                handle_task(app_server_id, update_manager, app_manager, settings_manager)
-            {
-                warn!("handle update_task failed, err: {:?}", e);
-            }
+    let data_path = match get_data_path_by_app_server_id(
+        app_server_id,
+        Arc::clone(&app_manager),
+        Arc::clone(&settings_manager),
+    ) {
+        Ok(p) => p,
+        Err(_) => {
+            warn!("get_data_path_by_app_server_id failed");
+            return;
         }
-        UpdateTaskControlMessage::Stop { app_server_id } => {
-            let mut update_manager_g = update_manager.lock().unwrap();
-            let update_task_o =
-                update_manager_g.get_mut_update_task_by_app_server_id(app_server_id);
-            match update_task_o {
-                None => {}
-                Some(update_task) => {
-                    if let Err(e) = update_task.tx.send(TaskControlMessage::Stop) {
-                        warn!(
-                            "send TaskControlMessage::Stop to channel failed, err: {}",
-                            e
-                        );
-                    }
-                }
+    };
+    pause::remove(&data_path);
+    if let Err(e) = staging::discard(&data_path) {
+        warn!(
+            "discard staging failed, data_path: {}, err: {:?}",
+            data_path, e
+        );
+    }
+    info!("paused update discarded, app_server_id: {}", app_server_id);
+}
+
+fn send_task_control_message(
+    app_server_id: u64,
+    message: TaskControlMessage,
+    update_manager: Arc<Mutex<UpdateManager>>,
+) {
+    let mut update_manager_g = update_manager.lock().unwrap();
+    let update_task_o = update_manager_g.get_mut_update_task_by_app_server_id(app_server_id);
+    match update_task_o {
+        None => {}
+        Some(update_task) => {
+            let id = update_task.id;
+            let is_stop = matches!(message, TaskControlMessage::Stop);
+            if let Err(e) = update_task.tx.send(message) {
+                warn!("send TaskControlMessage to channel failed, err: {}", e);
+            }
+            // a queued task is not started any more
+            if is_stop {
+                update_manager_g.dequeue_task(id);
             }
-            drop(update_manager_g);
         }
     }
+    drop(update_manager_g);
 }
 
 fn handle_task(
     app_server_id: u64,
+    mode: UpdateMode,
+    priority: TaskPriority,
     update_manager: Arc<Mutex<UpdateManager>>,
     app_manager: Arc<Mutex<AppManager>>,
     settings_manager: Arc<Mutex<SettingsManager>>,
 ) -> Result<(), Error> {
+    // a failure is reported by do_handle_task, after the task is added
+    let data_path = get_data_path_by_app_server_id(
+        app_server_id,
+        Arc::clone(&app_manager),
+        Arc::clone(&settings_manager),
+    )
+    .unwrap_or_default();
     let mut update_manager_g = update_manager.lock().unwrap();
-    let task = update_manager_g.create_task(app_server_id);
+    let mut task = update_manager_g.create_task(app_server_id);
     let task_id = task.id;
-    task.tx
-        .send(TaskControlMessage::Start)
-        .map_err(|_| Error::SendControlMessageFailed)?;
+    task.priority = priority;
+    task.data_path = data_path;
+    // started by the scheduler of UpdateManager when there is a free slot
     if let Err(e) = update_manager_g.add_task(task) {
         warn!("add update task failed, task_id: {}", task_id);
         return Err(e);
@@ -94,29 +260,55 @@ fn handle_task(
     trace_tx
         .send(UpdateTaskTraceMessage::Wait)
         .map_err(|_| Error::SendTraceMessageFailed)?;
+    let mut record = UpdateRecord::new(app_server_id, mode);
     let r = do_handle_task(
-        app_server_id,
+        &mut record,
         update_manager,
-        app_manager,
+        Arc::clone(&app_manager),
         settings_manager,
         task_id,
         trace_tx.clone(),
     );
-    if let Err(_) = &r {
+    if let Err(e) = &r {
+        record.result = UpdateResult::Failed;
+        record.error = Some(format!("{:?}", e));
+    }
+    // previews and verifications change nothing, they are not kept
+    if matches!(
+        mode,
+        UpdateMode::Update | UpdateMode::Repair | UpdateMode::Resume
+    ) {
+        // a repair deletes nothing, it does not finish an update, see UpdateMode::Repair
+        if record.result == UpdateResult::Finished
+            && matches!(mode, UpdateMode::Update | UpdateMode::Resume)
+        {
+            check::set_update_available(app_server_id, false, app_manager);
+        }
+        record.end_time = chrono::Utc::now().timestamp();
+        history::add(record);
+    }
+    if let Err(e) = &r {
+        let space_shortage = match e {
+            Error::InsufficientDiskSpace(x) => Some(x.clone()),
+            _ => None,
+        };
         trace_tx
-            .send(UpdateTaskTraceMessage::Failed)
+            .send(UpdateTaskTraceMessage::Failed { space_shortage })
             .map_err(|_| Error::SendTraceMessageFailed)?;
     };
     r
 }
+// the record is filled as the task goes on
 fn do_handle_task(
-    app_server_id: u64,
+    record: &mut UpdateRecord,
     update_manager: Arc<Mutex<UpdateManager>>,
     app_manager: Arc<Mutex<AppManager>>,
     settings_manager: Arc<Mutex<SettingsManager>>,
     task_id: u64,
     trace_tx: Sender<UpdateTaskTraceMessage>,
 ) -> Result<(), Error> {
+    let app_server_id = record.app_server_id;
+    let mode = record.mode;
     // check start
     loop {
         thread::sleep(Duration::from_millis(100));
@@ -127,13 +319,25 @@ fn do_handle_task(
                     debug!("get message: {:?}", message);
                     break;
                 }
+                // canceled while queued
+                TaskControlMessage::Stop => {
+                    debug!("get message: {:?}", message);
+                    trace_tx
+                        .send(UpdateTaskTraceMessage::Canceled)
+                        .map_err(|_| Error::SendTraceMessageFailed)?;
+                    return Ok(());
+                }
                 _ => {}
             }
         }
     }
 
     let is_cancel = Arc::new(AtomicBool::new(false));
+    let is_confirmed = Arc::new(AtomicBool::new(false));
+    let is_paused = Arc::new(AtomicBool::new(false));
     let is_cancel_1 = Arc::clone(&is_cancel);
+    let is_confirmed_1 = Arc::clone(&is_confirmed);
+    let is_paused_1 = Arc::clone(&is_paused);
     let update_manager_1 = Arc::clone(&update_manager);
     let trace_tx_1 = trace_tx.clone();
     thread::spawn(move || loop {
@@ -149,6 +353,26 @@ fn do_handle_task(
                         .map_err(|_| Error::SendTraceMessageFailed)
                         .unwrap();
                 }
+                TaskControlMessage::Confirm => {
+                    debug!("get message: {:?}", message);
+                    is_confirmed_1.store(true, Ordering::Relaxed);
+                }
+                // only files are synced at a safe point, the work before is done again anyway
+                TaskControlMessage::Pause => {
+                    debug!("get message: {:?}", message);
+                    let update_manager_g = update_manager_1.lock().unwrap();
+                    let is_syncing = update_manager_g
+                        .get_update_task_by_id(task_id)
+                        .is_some_and(|x| matches!(x.status, UpdateTaskStatus::Processing { .. }));
+                    drop(update_manager_g);
+                    if is_syncing {
+                        is_paused_1.store(true, Ordering::Relaxed);
+                        is_cancel_1.store(true, Ordering::Relaxed);
+                    } else {
+                        // e.g. the sync finished while the button was pressed
+                        warn!("pause ignored, not syncing, task_id: {}", task_id);
+                    }
+                }
                 _ => {}
             }
         }
@@ -172,7 +396,18 @@ fn do_handle_task(
         return Err(Error::CreateDirFailed);
     }
 
+    // the last update may be interrupted while switching to the new files
+    if let Err(e) = staging::rollback(&data_path) {
+        warn!(
+            "roll back last update failed, data_path: {}, err: {:?}",
+            data_path, e
+        );
+        return Err(Error::RollbackFailed);
+    }
+
     let mut app_id = 0;
+    let mut app_code = "".to_string();
+    let mut keep_rules = vec![];
     let mut address = "".to_string();
     let app_manager_g = app_manager.lock().unwrap();
     let mut is_found = false;
@@ -181,6 +416,8 @@ fn do_handle_task(
             if app_server.id == app_server_id {
                 is_found = true;
                 app_id = app.id;
+                app_code = app.code.clone();
+                keep_rules = app.keep_rules.clone();
                 address = app_server.address.to_address_string();
                 break 'outer;
             }
@@ -196,27 +433,110 @@ fn do_handle_task(
         return Err(Error::GetAppServerFailed);
     }
 
+    if mode == UpdateMode::Resume {
+        let sync_tasks_o = pause::load(&data_path).and_then(|x| {
+            record.server_scan_time = x.server_scan_time;
+            record.added_files = x.added_files;
+            record.changed_files = x.changed_files;
+            record.deleted_files = x.deleted_files;
+            record.protected_files = x.protected_files;
+            x.to_sync_tasks(&data_path)
+        });
+        let sync_tasks = match sync_tasks_o {
+            Some(x) => x,
+            None => {
+                warn!(
+                    "load paused update failed, app_server_id: {}, data_path: {}",
+                    app_server_id, data_path
+                );
+                return Err(Error::LoadPausedUpdateFailed);
+            }
+        };
+        return sync_and_commit(
+            record, &address, &data_path, sync_tasks, is_cancel, is_paused, trace_tx,
+        );
+    }
+    // files staged by the paused update are still used by the new one
+    if matches!(mode, UpdateMode::Update | UpdateMode::Repair) {
+        pause::remove(&data_path);
+    }
+
     // get server files
     trace_tx
         .send(UpdateTaskTraceMessage::GetServerUpdateInfo)
         .map_err(|_| Error::SendTraceMessageFailed)?;
-    let sfi_r = request::app_server::file_info::get_file_info(&address);
-    let sfi = match sfi_r {
-        Ok(x) => ServerFileInfo::from(&x),
-        Err(e) => {
-            warn!(
-                "get ServerFileInfo failed, app_server_id: {}, err: {:?}",
-                app_server_id, e
-            );
-            return Err(Error::GetServerFileInfoFailed);
+    // the file list of a server still scanning is partial, good local files would be deleted
+    let wait_start_time = Instant::now();
+    let sfi = loop {
+        let sfi = get_server_file_info(app_server_id, &app_code, &address)?;
+        match sfi.scan_status {
+            ScanStatus::Completed => break sfi,
+            ScanStatus::Failed => {
+                warn!("server scan failed, app_server_id: {}", app_server_id);
+                return Err(Error::ServerScanFailed);
+            }
+            ScanStatus::Wait | ScanStatus::Scanning => {
+                let waited_secs = wait_start_time.elapsed().as_secs();
+                if waited_secs >= CONFIG.update.server_scan_timeout {
+                    warn!(
+                        "wait for server scan timed out, app_server_id: {}, waited_secs: {}",
+                        app_server_id, waited_secs
+                    );
+                    return Err(Error::ServerScanTimeout);
+                }
+                debug!(
+                    "wait for server scan, app_server_id: {}, scan_status: {:?}",
+                    app_server_id, sfi.scan_status
+                );
+                trace_tx
+                    .send(UpdateTaskTraceMessage::WaitServerScan { waited_secs })
+                    .map_err(|_| Error::SendTraceMessageFailed)?;
+                let poll_start_time = Instant::now();
+                while poll_start_time.elapsed() < SERVER_SCAN_POLL_INTERVAL {
+                    if is_cancel.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
         }
     };
 
+    record.server_scan_time = sfi.last_scan_finish_time;
+
+    let settings_manager_g = settings_manager.lock().unwrap();
+    let is_deep_verify = settings_manager_g.settings.update_settings.is_deep_verify
+        || matches!(mode, UpdateMode::Verify | UpdateMode::Repair);
+    drop(settings_manager_g);
+
     // scan local files
     trace_tx
-        .send(UpdateTaskTraceMessage::GetClientFileInfo)
+        .send(UpdateTaskTraceMessage::GetClientFileInfo {
+            progress: Default::default(),
+        })
         .map_err(|_| Error::SendTraceMessageFailed)?;
-    let cfi_r = scan::scan(&data_path, Arc::clone(&is_cancel));
+    let scan_counter = Arc::new(ScanCounter::default());
+    let is_scan_done = Arc::new(AtomicBool::new(false));
+    let scan_counter_1 = Arc::clone(&scan_counter);
+    let is_scan_done_1 = Arc::clone(&is_scan_done);
+    let trace_tx_2 = trace_tx.clone();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(200));
+        if is_scan_done_1.load(Ordering::Relaxed) {
+            break;
+        }
+        let _ = trace_tx_2.send(UpdateTaskTraceMessage::GetClientFileInfo {
+            progress: scan_counter_1.get_progress(),
+        });
+    });
+    let cfi_r = scan::scan_incremental(
+        &data_path,
+        sfi.hash_algorithm,
+        is_deep_verify,
+        &scan_counter,
+        Arc::clone(&is_cancel),
+    );
+    is_scan_done.store(true, Ordering::Relaxed);
     let cfi = match cfi_r {
         Ok(x) => x,
         Err(e) => {
@@ -234,14 +554,177 @@ fn do_handle_task(
     };
 
     // diff files
-    let (added_files, changed_files, deleted_files) = diff_files(&cfi, &sfi);
+    let (added_files, mut changed_files, mut deleted_files, mut metadata_changed_files) =
+        diff::diff_files(&cfi.files, &sfi.files, CasePolicy::native());
+    if let Some(app_config) = CONFIG.get_app_config(&app_code) {
+        keep_rules.extend(app_config.keep_rules.iter().cloned());
+    }
+    let keep_rules = KeepRules::new(&keep_rules);
+    let protected_files = keep_rules.apply(&mut changed_files, &mut deleted_files);
+    metadata_changed_files.retain(|x| !keep_rules.is_no_overwrite(&x.relative_path));
     debug!(
-        "sfi: {:?}, cfi: {:?}, added_files: {:?}, changed_files: {:?}, deleted_files: {:?}",
-        sfi, cfi, added_files, changed_files, deleted_files
+        "sfi: {:?}, cfi: {:?}, added_files: {:?}, changed_files: {:?}, deleted_files: {:?}, \
+        metadata_changed_files: {:?}",
+        sfi, cfi, added_files, changed_files, deleted_files, metadata_changed_files
     );
     print_diff_detail(&sfi, &cfi, &added_files, &changed_files, &deleted_files);
 
-    let app_server_info_r = request::app_server::app_server_info::get_app_server_info(&address);
+    match mode {
+        UpdateMode::Verify => {
+            let modified_files = [changed_files, metadata_changed_files].concat();
+            let report = make_verify_report(&added_files, &modified_files, &deleted_files);
+            info!(
+                "verify report, app_server_id: {}, report: {:?}",
+                app_server_id, report
+            );
+            trace_tx
+                .send(UpdateTaskTraceMessage::Verified { report })
+                .map_err(|_| Error::SendTraceMessageFailed)?;
+            return Ok(());
+        }
+        UpdateMode::Repair => {
+            // extra files may be added by the player on purpose
+            deleted_files.clear();
+        }
+        _ => {}
+    }
+    record.added_files = added_files.len() as u64;
+    record.changed_files = (changed_files.len() + metadata_changed_files.len()) as u64;
+    record.deleted_files = deleted_files.len() as u64;
+    record.protected_files = protected_files;
+
+    let plan = make_update_plan(
+        &added_files,
+        &changed_files,
+        &metadata_changed_files,
+        &deleted_files,
+        protected_files,
+    );
+    info!(
+        "update plan, app_server_id: {}, plan: {:?}",
+        app_server_id, plan
+    );
+    if mode == UpdateMode::Preview {
+        trace_tx
+            .send(UpdateTaskTraceMessage::Planned { plan })
+            .map_err(|_| Error::SendTraceMessageFailed)?;
+        return Ok(());
+    }
+    // deleting files can not be undone by the user, ask first, a repair is asked for by the user
+    // after seeing the report
+    if mode == UpdateMode::Update
+        && (!plan.deleted_files.is_empty() || CONFIG.update.always_confirm)
+    {
+        trace_tx
+            .send(UpdateTaskTraceMessage::AwaitingConfirmation { plan })
+            .map_err(|_| Error::SendTraceMessageFailed)?;
+        while !is_confirmed.load(Ordering::Relaxed) {
+            if is_cancel.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    let sync_tasks = generate_sync_tasks(
+        app_id,
+        &added_files,
+        &changed_files,
+        &metadata_changed_files,
+        &deleted_files,
+        &data_path,
+    );
+    sync_and_commit(
+        record, &address, &data_path, sync_tasks, is_cancel, is_paused, trace_tx,
+    )
+}
+
+fn get_server_file_info(
+    app_server_id: u64,
+    app_code: &str,
+    address: &str,
+) -> Result<ServerFileInfo, Error> {
+    let sfi_r = request::app_server::file_info::get_file_info(address);
+    match sfi_r {
+        Ok(x) => {
+            let app_config_o = CONFIG.get_app_config(app_code);
+            let signature_r =
+                signature::verify_manifest(app_config_o, x.body.as_bytes(), x.signature.as_deref());
+            match signature_r {
+                Ok(_) => {}
+                // e.g. installed before signing was required, see config.toml
+                Err(SignatureError::NoPublicKey) => {
+                    let config_key = format!("apps.{}.public_keys", app_code);
+                    warn!(
+                        "no public key to verify ServerFileInfo, set {} or apps.{}.allow_unsigned, app_server_id: {}",
+                        config_key, app_code, app_server_id
+                    );
+                    return Err(Error::NoPublicKey(config_key));
+                }
+                Err(e) => {
+                    warn!(
+                        "verify signature of ServerFileInfo failed, app_server_id: {}, app_code: {}, err: {:?}",
+                        app_server_id, app_code, e
+                    );
+                    return Err(Error::VerifySignatureFailed);
+                }
+            }
+            ServerFileInfo::try_from(&x.data).map_err(|e| {
+                warn!(
+                    "invalid ServerFileInfo, app_server_id: {}, err: {}",
+                    app_server_id, e
+                );
+                Error::GetServerFileInfoFailed
+            })
+        }
+        Err(e) => {
+            warn!(
+                "get ServerFileInfo failed, app_server_id: {}, err: {:?}",
+                app_server_id, e
+            );
+            Err(Error::GetServerFileInfoFailed)
+        }
+    }
+}
+
+// syncs to the staging area and switches the data dir to it, the SyncTasks are kept if paused
+fn sync_and_commit(
+    record: &mut UpdateRecord,
+    address: &str,
+    data_path: &str,
+    sync_tasks: Vec<SyncTask>,
+    is_cancel: Arc<AtomicBool>,
+    is_paused: Arc<AtomicBool>,
+    trace_tx: Sender<UpdateTaskTraceMessage>,
+) -> Result<(), Error> {
+    let app_server_id = record.app_server_id;
+    // every path is checked before anything is touched, the space check stats staged files and
+    // deleted and metadata changed files are not handled by the workers
+    for task in &sync_tasks {
+        let relative_path = &task.file_info.relative_path;
+        let mut r = update::sync::get_contained_path(&task.base_path, relative_path);
+        if r.is_ok() && matches!(task.sync_type, SyncTaskType::Create | SyncTaskType::Update) {
+            r = update::sync::get_contained_path(&task.staging_path, relative_path);
+        }
+        if let Err(e) = r {
+            warn!(
+                "path rejected, app_server_id: {}, err: {:?}, file_info: {:?}",
+                app_server_id, e, task.file_info
+            );
+            return Err(Error::HandleSyncTaskFailed);
+        }
+    }
+    // fail before anything is downloaded, instead of when the volume is full
+    let required_space = space::get_required_space(&sync_tasks, &cache::get_cached_files());
+    if let Err(e) = space::check_free_space(data_path, &required_space) {
+        warn!(
+            "insufficient disk space, app_server_id: {}, required_space: {:?}, err: {:?}",
+            app_server_id, required_space, e
+        );
+        return Err(Error::InsufficientDiskSpace(e));
+    }
+
+    let app_server_info_r = request::app_server::app_server_info::get_app_server_info(address);
     let data_nodes = match app_server_info_r {
         Ok(app_server_info) => app_server_info
             .data_nodes
@@ -257,16 +740,124 @@ fn do_handle_task(
         }
     };
 
-    let sync_tasks = generate_sync_tasks(
-        app_id,
-        &added_files,
-        &changed_files,
-        &deleted_files,
-        &data_path,
-        &data_nodes,
+    let data_node_selector = Arc::new(DataNodeSelector::new(data_nodes));
+
+    // new files are synced to the staging area by a pool of workers, the data dir is switched
+    // to them after all of them succeeded
+    let sync_tasks_to_run = sync_tasks
+        .iter()
+        .filter(|x| {
+            !matches!(
+                x.sync_type,
+                SyncTaskType::Delete | SyncTaskType::UpdateMetadata
+            )
+        })
+        .cloned()
+        .collect();
+    let done_bytes = Arc::new(AtomicU64::new(0));
+    let sync_r = run_sync_tasks(
+        sync_tasks_to_run,
+        CONFIG.update.sync_workers,
+        Arc::clone(&data_node_selector),
+        Arc::clone(&done_bytes),
+        Arc::clone(&is_cancel),
+        trace_tx.clone(),
+    );
+    record.transferred_bytes = done_bytes.load(Ordering::Relaxed);
+    sync_r?;
+
+    if is_cancel.load(Ordering::Relaxed) {
+        if is_paused.load(Ordering::Relaxed) {
+            pause::save(data_path, &PausedUpdate::new(&sync_tasks, record));
+            record.result = UpdateResult::Paused;
+            trace_tx
+                .send(UpdateTaskTraceMessage::Paused)
+                .map_err(|_| Error::SendTraceMessageFailed)?;
+        }
+        return Ok(());
+    }
+    if let Err(e) = staging::commit(data_path, &sync_tasks) {
+        warn!(
+            "commit staging failed, app_server_id: {}, err: {:?}",
+            app_server_id, e
+        );
+        return Err(Error::CommitFailed);
+    }
+    pause::remove(data_path);
+    apply_metadata(data_path, &sync_tasks);
+    update_scan_index(data_path, &sync_tasks);
+    info!(
+        "all sync task finished, app_server_id: {}, data_node_stats: {:?}",
+        app_server_id,
+        data_node_selector.get_stats()
     );
+    record.result = UpdateResult::Finished;
+    trace_tx
+        .send(UpdateTaskTraceMessage::Finished {
+            finish_time: chrono::Utc::now().timestamp(),
+            protected_files: record.protected_files,
+        })
+        .map_err(|_| Error::SendTraceMessageFailed)?;
+    Ok(())
+}
 
-    let total = added_files.len() + changed_files.len();
+// the data dir is switched already, a failure is logged and found again by the next update
+fn apply_metadata(data_path: &str, sync_tasks: &[SyncTask]) {
+    for task in sync_tasks
+        .iter()
+        .filter(|x| matches!(x.sync_type, SyncTaskType::UpdateMetadata))
+    {
+        let p = Path::new(data_path).join(&task.file_info.relative_path);
+        if let Err(e) = metadata::unshare(&p).and_then(|_| metadata::apply(&task.file_info, &p)) {
+            warn!("apply metadata failed, path: {:?}, err: {}", p, e);
+        }
+    }
+}
+
+// synced files are known by hash already, they are not hashed again by the next scan
+fn update_scan_index(data_path: &str, sync_tasks: &[SyncTask]) {
+    let hash_algorithm = match sync_tasks.first() {
+        None => return,
+        Some(x) => x.file_info.hash_algorithm,
+    };
+    let mut scan_index = index::load(data_path, hash_algorithm);
+    for task in sync_tasks {
+        let relative_path = &task.file_info.relative_path;
+        match task.sync_type {
+            SyncTaskType::Delete => {
+                scan_index.remove(relative_path);
+            }
+            // the mtime may have been changed
+            SyncTaskType::Create | SyncTaskType::Update | SyncTaskType::UpdateMetadata => {
+                if task.file_info.file_type != FileType::File {
+                    continue;
+                }
+                let p = Path::new(data_path).join(relative_path);
+                match fs::symlink_metadata(&p) {
+                    Ok(m) if m.is_file() => {
+                        let mtime = index::get_mtime(&m).unwrap_or_default();
+                        scan_index.insert(relative_path, m.len(), mtime, &task.file_info.hash);
+                    }
+                    _ => scan_index.remove(relative_path),
+                }
+            }
+        }
+    }
+    index::save(data_path, &scan_index);
+}
+
+fn run_sync_tasks(
+    sync_tasks: Vec<SyncTask>,
+    worker_count: usize,
+    data_node_selector: Arc<DataNodeSelector>,
+    done_bytes: Arc<AtomicU64>,
+    is_cancel: Arc<AtomicBool>,
+    trace_tx: Sender<UpdateTaskTraceMessage>,
+) -> Result<(), Error> {
+    let worker_count = worker_count.clamp(1, sync_tasks.len().max(1));
+    let total = sync_tasks.iter().map(|x| x.file_info.size).sum();
+    // the last started one is shown
+    let current_sync_task: Arc<Mutex<Option<SyncTask>>> = Arc::new(Mutex::new(None));
 
     let (sync_task_tx, sync_task_rx) = mpsc::channel::<SyncTask>();
     debug!("add SyncTask to channel");
@@ -280,62 +871,154 @@ fn do_handle_task(
     // close and rx will recv disconnect err when channel empty
     drop(sync_task_tx);
 
-    let mut count = 0; // exclude deleted SyncTask (added + changed)
-    loop {
-        thread::sleep(Duration::from_millis(10));
-
-        if is_cancel.load(Ordering::Relaxed) {
-            break;
-        }
+    let sync_task_rx = Arc::new(Mutex::new(sync_task_rx));
+    let sync_error: Arc<Mutex<Option<SyncError>>> = Arc::new(Mutex::new(None));
+    let mut workers = vec![];
+    for _ in 0..worker_count {
+        let sync_task_rx = Arc::clone(&sync_task_rx);
+        let sync_error = Arc::clone(&sync_error);
+        let data_node_selector = Arc::clone(&data_node_selector);
+        let done_bytes = Arc::clone(&done_bytes);
+        let current_sync_task = Arc::clone(&current_sync_task);
+        let is_cancel = Arc::clone(&is_cancel);
+        workers.push(thread::spawn(move || loop {
+            if is_cancel.load(Ordering::Relaxed) {
+                return;
+            }
 
-        // handle
-        debug!("get SyncTask");
-        let sync_task_r = sync_task_rx.recv_timeout(Duration::from_millis(100));
-        match sync_task_r {
-            Ok(sync_task) => {
-                match sync_task.sync_type {
-                    SyncTaskType::Create | SyncTaskType::Update => {
-                        count += 1;
-                    }
-                    _ => {}
+            // handle
+            debug!("get SyncTask");
+            let sync_task_r = sync_task_rx.lock().unwrap().recv();
+            let sync_task = match sync_task_r {
+                Ok(x) => x,
+                Err(_) => {
+                    return;
                 }
-                trace_tx
-                    .send(UpdateTaskTraceMessage::Processing {
-                        progress: Progress::new(count, total as u64),
-                        sync_task: sync_task.clone(),
-                    })
-                    .map_err(|_| Error::SendTraceMessageFailed)?;
-                if let Err(e) = update::sync::handle_task(sync_task, Arc::clone(&is_cancel)) {
-                    return match e {
-                        // canceled by control message
-                        SyncError::Cancel => Ok(()),
-                        _ => {
-                            warn!("handle SyncTask failed, err: {:?}", e);
-                            Err(Error::HandleSyncTaskFailed)
+            };
+            *current_sync_task.lock().unwrap() = Some(sync_task.clone());
+            let task_progress =
+                TaskProgress::new(Arc::clone(&done_bytes), sync_task.file_info.size);
+            let r = update::sync::handle_task(
+                sync_task,
+                Arc::clone(&data_node_selector),
+                &task_progress,
+                Arc::clone(&is_cancel),
+            );
+            if let Err(e) = r {
+                match e {
+                    // canceled by control message
+                    SyncError::Cancel => {}
+                    _ => {
+                        warn!("handle SyncTask failed, err: {:?}", e);
+                        let mut sync_error_g = sync_error.lock().unwrap();
+                        if sync_error_g.is_none() {
+                            *sync_error_g = Some(e);
                         }
-                    };
+                        drop(sync_error_g);
+                        // stop other workers
+                        is_cancel.store(true, Ordering::Relaxed);
+                    }
                 }
+                return;
+            }
+            task_progress.finish();
+        }));
+    }
+
+    let is_done = Arc::new(AtomicBool::new(false));
+    let is_done_1 = Arc::clone(&is_done);
+    let reporter = thread::spawn(move || {
+        let mut speed_meter = SpeedMeter::new();
+        loop {
+            let value = done_bytes.load(Ordering::Relaxed);
+            let speed = speed_meter.update(value);
+            let sync_task_o = current_sync_task.lock().unwrap().clone();
+            if let Some(sync_task) = sync_task_o {
+                let mut progress = Progress::new(value, total);
+                progress.speed = speed;
+                progress.remaining_time =
+                    speed_meter.get_remaining_time(total.saturating_sub(value));
+                let _ = trace_tx.send(UpdateTaskTraceMessage::Processing {
+                    progress,
+                    sync_task,
+                    data_node_stats: data_node_selector.get_stats(),
+                });
+            }
+            thread::sleep(Duration::from_millis(500));
+            if is_done_1.load(Ordering::Relaxed) {
+                break;
             }
-            Err(e) => match e {
-                RecvTimeoutError::Timeout => {
-                    // debug!("timeout");
-                }
-                RecvTimeoutError::Disconnected => {
-                    info!("all sync task finished, app_server_id: {}", app_server_id);
-                    trace_tx
-                        .send(UpdateTaskTraceMessage::Finished {
-                            finish_time: chrono::Utc::now().timestamp(),
-                        })
-                        .map_err(|_| Error::SendTraceMessageFailed)?;
-                    return Ok(());
-                }
-            },
+        }
+    });
+
+    let mut is_panicked = false;
+    for x in workers {
+        if x.join().is_err() {
+            is_panicked = true;
         }
     }
+    is_done.store(true, Ordering::Relaxed);
+    let _ = reporter.join();
+    if is_panicked {
+        warn!("sync worker panicked");
+        return Err(Error::HandleSyncTaskFailed);
+    }
 
+    let sync_error_g = sync_error.lock().unwrap();
+    if sync_error_g.is_some() {
+        return Err(Error::HandleSyncTaskFailed);
+    }
+    drop(sync_error_g);
     Ok(())
 }
 
+// transfer rate smoothed by an exponential moving average, so that it does not jump between
+// files and data nodes
+struct SpeedMeter {
+    last_value: u64,
+    last_time: Instant,
+    // bytes per second
+    speed: Option<f64>,
+}
+
+impl SpeedMeter {
+    // weight of the latest sample
+    const SMOOTHING: f64 = 0.3;
+
+    fn new() -> Self {
+        Self {
+            last_value: 0,
+            last_time: Instant::now(),
+            speed: None,
+        }
+    }
+
+    fn update(&mut self, value: u64) -> u64 {
+        self.update_at(value, Instant::now())
+    }
+
+    fn update_at(&mut self, value: u64, now: Instant) -> u64 {
+        let elapsed = now.duration_since(self.last_time).as_secs_f64();
+        if elapsed < 0.1 {
+            return self.speed.unwrap_or_default() as u64;
+        }
+        let sample = value.saturating_sub(self.last_value) as f64 / elapsed;
+        let speed = match self.speed {
+            None => sample,
+            Some(x) => Self::SMOOTHING * sample + (1.0 - Self::SMOOTHING) * x,
+        };
+        self.speed = Some(speed);
+        self.last_value = value;
+        self.last_time = now;
+        speed as u64
+    }
+
+    // seconds to transfer the remaining bytes at the current speed, not known while stalled
+    fn get_remaining_time(&self, remaining_bytes: u64) -> Option<u64> {
+        remaining_bytes.checked_div(self.speed? as u64)
+    }
+}
+
 pub fn get_control_message(
     task_id: u64,
     update_manager: Arc<Mutex<UpdateManager>>,
@@ -387,26 +1070,49 @@ fn watch_trace(
                             UpdateTaskTraceMessage::GetServerUpdateInfo => {
                                 task.status = UpdateTaskStatus::GetServerUpdateInfo;
                             }
-                            UpdateTaskTraceMessage::GetClientFileInfo => {
-                                task.status = UpdateTaskStatus::GetClientFileInfo;
+                            UpdateTaskTraceMessage::WaitServerScan { waited_secs } => {
+                                task.status = UpdateTaskStatus::WaitServerScan { waited_secs };
+                            }
+                            UpdateTaskTraceMessage::GetClientFileInfo { progress } => {
+                                task.status = UpdateTaskStatus::GetClientFileInfo { progress };
                             }
                             UpdateTaskTraceMessage::Processing {
                                 progress,
                                 sync_task,
+                                data_node_stats,
                             } => {
                                 task.status = UpdateTaskStatus::Processing {
                                     progress,
                                     sync_task,
+                                    data_node_stats,
                                 };
                             }
+                            UpdateTaskTraceMessage::AwaitingConfirmation { plan } => {
+                                task.status = UpdateTaskStatus::AwaitingConfirmation { plan };
+                            }
+                            UpdateTaskTraceMessage::Planned { plan } => {
+                                task.status = UpdateTaskStatus::Planned { plan };
+                            }
+                            UpdateTaskTraceMessage::Verified { report } => {
+                                task.status = UpdateTaskStatus::Verified { report };
+                            }
+                            UpdateTaskTraceMessage::Paused => {
+                                task.status = UpdateTaskStatus::Paused;
+                            }
                             UpdateTaskTraceMessage::Canceled => {
                                 task.status = UpdateTaskStatus::Canceled;
                             }
-                            UpdateTaskTraceMessage::Failed => {
-                                task.status = UpdateTaskStatus::Failed;
+                            UpdateTaskTraceMessage::Failed { space_shortage } => {
+                                task.status = UpdateTaskStatus::Failed { space_shortage };
                             }
-                            UpdateTaskTraceMessage::Finished { finish_time } => {
-                                task.status = UpdateTaskStatus::Finished { finish_time };
+                            UpdateTaskTraceMessage::Finished {
+                                finish_time,
+                                protected_files,
+                            } => {
+                                task.status = UpdateTaskStatus::Finished {
+                                    finish_time,
+                                    protected_files,
+                                };
                             }
                         }
                         drop(update_manager_g);
@@ -421,55 +1127,6 @@ fn watch_trace(
     }
 }
 
-fn diff_files(
-    cfi: &ClientFileInfo,
-    sfi: &ServerFileInfo,
-) -> (Vec<FileInfo>, Vec<FileInfo>, Vec<FileInfo>) {
-    let mut added_files: Vec<FileInfo> = vec![];
-    let mut changed_files: Vec<FileInfo> = vec![];
-    let mut deleted_files: Vec<FileInfo> = vec![];
-
-    for cf in &cfi.files {
-        if !is_in(cf, &sfi.files) {
-            deleted_files.push(cf.clone());
-        }
-    }
-    for sf in &sfi.files {
-        if !is_in(sf, &cfi.files) {
-            added_files.push(sf.clone());
-        }
-    }
-    for sf in &sfi.files {
-        let sf_path = Path::new(&sf.relative_path);
-        for cf in &cfi.files {
-            let cf_path = Path::new(&cf.relative_path);
-            if cf_path.eq(sf_path) {
-                if cf.file_type != sf.file_type || cf.size != sf.size || cf.hash != sf.hash {
-                    changed_files.push(sf.clone());
-                }
-                break;
-            }
-        }
-    }
-    (added_files, changed_files, deleted_files)
-}
-
-fn is_in(f: &FileInfo, files: &Vec<FileInfo>) -> bool {
-    let f_path = Path::new(&f.relative_path);
-    let mut flag = false;
-    for x in files {
-        let x_path = Path::new(&x.relative_path);
-        if x_path.eq(f_path) {
-            flag = true;
-            break;
-        }
-    }
-    if flag {
-        return true;
-    }
-    false
-}
-
 fn print_diff_detail(
     sfi: &ServerFileInfo,
     cfi: &ClientFileInfo,
@@ -498,14 +1155,56 @@ fn print_file_info(fi: &Vec<FileInfo>, s: &str) {
     debug!("------- {} -------", s);
 }
 
+// metadata changed files are counted as changed, they are not downloaded
+fn make_update_plan(
+    added_files: &[FileInfo],
+    changed_files: &[FileInfo],
+    metadata_changed_files: &[FileInfo],
+    deleted_files: &[FileInfo],
+    protected_files: u64,
+) -> UpdatePlan {
+    let download_bytes = added_files
+        .iter()
+        .chain(changed_files)
+        .filter(|x| x.file_type == FileType::File)
+        .map(|x| x.size)
+        .sum();
+    UpdatePlan {
+        added_files: added_files.len() as u64,
+        changed_files: (changed_files.len() + metadata_changed_files.len()) as u64,
+        deleted_files: deleted_files
+            .iter()
+            .map(|x| x.relative_path.clone())
+            .collect(),
+        download_bytes,
+        protected_files,
+    }
+}
+
+fn make_verify_report(
+    added_files: &[FileInfo],
+    changed_files: &[FileInfo],
+    deleted_files: &[FileInfo],
+) -> VerifyReport {
+    let to_paths = |files: &[FileInfo]| -> Vec<String> {
+        files.iter().map(|x| x.relative_path.clone()).collect()
+    };
+    VerifyReport {
+        missing_files: to_paths(added_files),
+        modified_files: to_paths(changed_files),
+        extra_files: to_paths(deleted_files),
+    }
+}
+
 fn generate_sync_tasks(
     app_id: u64,
     added_files: &Vec<FileInfo>,
     changed_files: &Vec<FileInfo>,
+    metadata_changed_files: &Vec<FileInfo>,
     deleted_files: &Vec<FileInfo>,
     base_path: &str,
-    data_nodes: &Vec<DataNode>,
 ) -> Vec<SyncTask> {
+    let staging_path = staging::get_staging_path(base_path);
     let mut tasks = vec![];
     for fi in added_files {
         tasks.push(SyncTask::new(
@@ -513,7 +1212,7 @@ fn generate_sync_tasks(
             SyncTaskType::Create,
             fi.clone(),
             base_path.to_string(),
-            data_nodes.clone(),
+            staging_path.clone(),
         ));
     }
     for fi in changed_files {
@@ -522,7 +1221,16 @@ fn generate_sync_tasks(
             SyncTaskType::Update,
             fi.clone(),
             base_path.to_string(),
-            data_nodes.clone(),
+            staging_path.clone(),
+        ));
+    }
+    for fi in metadata_changed_files {
+        tasks.push(SyncTask::new(
+            app_id,
+            SyncTaskType::UpdateMetadata,
+            fi.clone(),
+            base_path.to_string(),
+            staging_path.clone(),
         ));
     }
     for fi in deleted_files {
@@ -531,8 +1239,39 @@ fn generate_sync_tasks(
             SyncTaskType::Delete,
             fi.clone(),
             base_path.to_string(),
-            data_nodes.clone(),
+            staging_path.clone(),
         ));
     }
     tasks
 }
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::update::SpeedMeter;
+    use std::time::Duration;
+
+    #[test]
+    fn test_speed_meter_remaining_time() {
+        let mut speed_meter = SpeedMeter::new();
+        let start_time = speed_meter.last_time;
+        let at = |ms| start_time + Duration::from_millis(ms);
+        assert_eq!(speed_meter.get_remaining_time(1000), None);
+
+        // too soon for a sample
+        assert_eq!(speed_meter.update_at(500, at(50)), 0);
+        assert_eq!(speed_meter.update_at(1000, at(1000)), 1000);
+        assert_eq!(speed_meter.get_remaining_time(10_000), Some(10));
+        // smoothed, 0.3 * 2000 + 0.7 * 1000
+        assert_eq!(speed_meter.update_at(3000, at(2000)), 1300);
+        assert_eq!(speed_meter.get_remaining_time(13_000), Some(10));
+
+        // stalled, the speed drops towards 0 and the remaining time grows
+        let mut last_remaining_time = 10;
+        for i in 3..20 {
+            speed_meter.update_at(3000, at(i * 1000));
+            let remaining_time = speed_meter.get_remaining_time(13_000).unwrap_or(u64::MAX);
+            assert!(remaining_time >= last_remaining_time);
+            last_remaining_time = remaining_time;
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use crate::application::app::AppManager;
 use crate::application::settings::SettingsManager;
 use crate::application::update::update::handle_update_control;
-use crate::application::update::{Error, UpdateTask, UpdateTaskControlMessage, UpdateTaskStatus};
+use crate::application::update::{check, staging};
+use crate::application::update::{
+    Error, TaskControlMessage, TaskPriority, UpdateTask, UpdateTaskControlMessage, UpdateTaskStatus,
+};
+use crate::config::CONFIG;
 use log::{debug, warn};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -13,6 +17,8 @@ use std::time::Duration;
 pub struct UpdateManager {
     next_update_task_id: AtomicU64,
     pub update_tasks: HashMap<u64, UpdateTask>,
+    // ids of tasks waiting for a free slot, the first one starts next
+    queue: Vec<u64>,
     operate_task_mutex: Mutex<()>,
     tx: Sender<UpdateTaskControlMessage>,
     rx: Receiver<UpdateTaskControlMessage>,
@@ -46,9 +52,29 @@ impl UpdateManager {
             .map(|(_, task)| task)
     }
 
+    // the task is queued behind tasks of the same or a higher priority, it is rejected if a
+    // queued or running task is for the same app server or data path
     pub fn add_task(&mut self, update_task: UpdateTask) -> Result<(), Error> {
         let mu = self.operate_task_mutex.lock().unwrap();
-        // TODO check duplicate by app_server_id
+        let is_duplicate = self.update_tasks.values().any(|x| {
+            let is_same_target = x.app_server_id == update_task.app_server_id
+                || (!x.data_path.is_empty() && x.data_path == update_task.data_path);
+            is_same_target && is_active(x)
+        });
+        if is_duplicate {
+            drop(mu);
+            return Err(Error::DuplicateTask);
+        }
+        let position = self
+            .queue
+            .iter()
+            .position(|x| {
+                self.update_tasks
+                    .get(x)
+                    .is_some_and(|x| x.priority < update_task.priority)
+            })
+            .unwrap_or(self.queue.len());
+        self.queue.insert(position, update_task.id);
         self.update_tasks.insert(update_task.id, update_task);
         drop(mu);
         Ok(())
@@ -57,12 +83,91 @@ impl UpdateManager {
     pub fn remove_task(&mut self, id: u64) -> Result<(), Error> {
         let mu = self.operate_task_mutex.lock().unwrap();
         self.update_tasks.remove(&id);
+        self.queue.retain(|x| *x != id);
         drop(mu);
         Ok(())
     }
 
-    pub fn start_task(&self, app_server_id: u64) -> Result<(), Error> {
-        let update_task_control_message = UpdateTaskControlMessage::Start { app_server_id };
+    // shows an update paused before the restart, it is not queued until resumed
+    pub fn add_paused_task(&mut self, app_server_id: u64) {
+        if self
+            .get_update_task_by_app_server_id(app_server_id)
+            .is_some()
+        {
+            return;
+        }
+        let mut task = self.create_task(app_server_id);
+        task.status = UpdateTaskStatus::Paused;
+        let mu = self.operate_task_mutex.lock().unwrap();
+        self.update_tasks.insert(task.id, task);
+        drop(mu);
+    }
+
+    // a canceled task does not take a slot
+    pub fn dequeue_task(&mut self, id: u64) {
+        let mu = self.operate_task_mutex.lock().unwrap();
+        self.queue.retain(|x| *x != id);
+        drop(mu);
+    }
+
+    // 1-based position and length of the queue, None if the task is not queued
+    pub fn get_queue_position(&self, app_server_id: u64) -> Option<(usize, usize)> {
+        let task = self.get_update_task_by_app_server_id(app_server_id)?;
+        let index = self.queue.iter().position(|x| *x == task.id)?;
+        Some((index + 1, self.queue.len()))
+    }
+
+    // moves a queued task one place to the front or the back, it takes the priority of the task
+    // it passes, so that tasks queued later are placed after it as expected
+    pub fn move_queued_task(&mut self, app_server_id: u64, is_forward: bool) {
+        let mu = self.operate_task_mutex.lock().unwrap();
+        let index_o = self
+            .get_update_task_by_app_server_id(app_server_id)
+            .and_then(|task| self.queue.iter().position(|x| *x == task.id));
+        let index = match index_o {
+            None => return,
+            Some(x) => x,
+        };
+        let other_index = match is_forward {
+            true if index > 0 => index - 1,
+            false if index + 1 < self.queue.len() => index + 1,
+            _ => return,
+        };
+        self.queue.swap(index, other_index);
+        let other_priority = self.update_tasks[&self.queue[index]].priority;
+        if let Some(task) = self.update_tasks.get_mut(&self.queue[other_index]) {
+            task.priority = other_priority;
+        }
+        drop(mu);
+    }
+
+    // starts queued tasks until max_concurrent_tasks are running
+    fn schedule(&mut self, max_concurrent_tasks: usize) {
+        let mu = self.operate_task_mutex.lock().unwrap();
+        let mut running_count = self
+            .update_tasks
+            .values()
+            .filter(|x| !self.queue.contains(&x.id) && is_taking_slot(x))
+            .count();
+        while running_count < max_concurrent_tasks.max(1) && !self.queue.is_empty() {
+            let id = self.queue.remove(0);
+            if let Some(task) = self.update_tasks.get(&id) {
+                if let Err(e) = task.tx.send(TaskControlMessage::Start) {
+                    warn!("send TaskControlMessage to channel failed, err: {}", e);
+                    continue;
+                }
+                debug!("update task started, task_id: {}", id);
+                running_count += 1;
+            }
+        }
+        drop(mu);
+    }
+
+    pub fn start_task(&self, app_server_id: u64, priority: TaskPriority) -> Result<(), Error> {
+        let update_task_control_message = UpdateTaskControlMessage::Start {
+            app_server_id,
+            priority,
+        };
         let r = self.tx.send(update_task_control_message);
         if let Err(e) = r {
             warn!("add UpdateTaskControlMessage failed, err: {}", e);
@@ -80,15 +185,73 @@ impl UpdateManager {
         Ok(())
     }
 
+    pub fn pause_task(&self, app_server_id: u64) -> Result<(), Error> {
+        let update_task_control_message = UpdateTaskControlMessage::Pause { app_server_id };
+        let r = self.tx.send(update_task_control_message);
+        if let Err(e) = r {
+            warn!("add UpdateTaskControlMessage failed, err: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn resume_task(&self, app_server_id: u64) -> Result<(), Error> {
+        let update_task_control_message = UpdateTaskControlMessage::Resume { app_server_id };
+        let r = self.tx.send(update_task_control_message);
+        if let Err(e) = r {
+            warn!("add UpdateTaskControlMessage failed, err: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn discard_task(&self, app_server_id: u64) -> Result<(), Error> {
+        let update_task_control_message = UpdateTaskControlMessage::Discard { app_server_id };
+        let r = self.tx.send(update_task_control_message);
+        if let Err(e) = r {
+            warn!("add UpdateTaskControlMessage failed, err: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn preview_task(&self, app_server_id: u64) -> Result<(), Error> {
+        let update_task_control_message = UpdateTaskControlMessage::Preview { app_server_id };
+        let r = self.tx.send(update_task_control_message);
+        if let Err(e) = r {
+            warn!("add UpdateTaskControlMessage failed, err: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn confirm_task(&self, app_server_id: u64) -> Result<(), Error> {
+        let update_task_control_message = UpdateTaskControlMessage::Confirm { app_server_id };
+        let r = self.tx.send(update_task_control_message);
+        if let Err(e) = r {
+            warn!("add UpdateTaskControlMessage failed, err: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn verify_task(&self, app_server_id: u64) -> Result<(), Error> {
+        let update_task_control_message = UpdateTaskControlMessage::Verify { app_server_id };
+        let r = self.tx.send(update_task_control_message);
+        if let Err(e) = r {
+            warn!("add UpdateTaskControlMessage failed, err: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn repair_task(&self, app_server_id: u64) -> Result<(), Error> {
+        let update_task_control_message = UpdateTaskControlMessage::Repair { app_server_id };
+        let r = self.tx.send(update_task_control_message);
+        if let Err(e) = r {
+            warn!("add UpdateTaskControlMessage failed, err: {}", e);
+        }
+        Ok(())
+    }
+
     pub fn is_update_processing(&self, app_server_id: u64) -> Result<bool, String> {
         let update_task_o = self.get_update_task_by_app_server_id(app_server_id);
         if let Some(update_task) = update_task_o {
-            return match update_task.status {
-                UpdateTaskStatus::Canceled
-                | UpdateTaskStatus::Failed
-                | UpdateTaskStatus::Finished { .. } => Ok(false),
-                _ => Ok(true),
-            };
+            return Ok(is_active(update_task));
         }
         Err(format!(
             "UpdateTask not exist, app_server_id: {}",
@@ -103,6 +266,7 @@ impl Default for UpdateManager {
         Self {
             next_update_task_id: AtomicU64::new(1),
             update_tasks: Default::default(),
+            queue: vec![],
             operate_task_mutex: Mutex::new(()),
             tx,
             rx,
@@ -115,27 +279,202 @@ pub fn start(
     app_manager: Arc<Mutex<AppManager>>,
     settings_manager: Arc<Mutex<SettingsManager>>,
 ) {
-    thread::spawn(move || loop {
-        thread::sleep(Duration::from_millis(100));
-        let update_manager_2 = Arc::clone(&update_manager);
-        let update_manager_g = update_manager.lock().unwrap();
-        let task_r = update_manager_g.rx.try_recv();
-        drop(update_manager_g);
-
-        match task_r {
-            Ok(message) => {
-                let app_manager = Arc::clone(&app_manager);
-                let settings_manager = Arc::clone(&settings_manager);
-                thread::spawn(move || {
-                    handle_update_control(message, update_manager_2, app_manager, settings_manager);
-                });
-            }
-            Err(e) => match e {
-                TryRecvError::Empty => {}
-                e => {
-                    debug!("get UpdateTaskControlMessage failed, err: {}", e)
+    check::start(Arc::clone(&update_manager), Arc::clone(&app_manager));
+
+    thread::spawn(move || {
+        // restore data dirs left in the middle of switching, before any update is started
+        let settings_manager_g = settings_manager.lock().unwrap();
+        let data_dir_path = settings_manager_g
+            .settings
+            .general_settings
+            .data_dir_path
+            .clone();
+        drop(settings_manager_g);
+        staging::rollback_all(&data_dir_path);
+
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            let update_manager_2 = Arc::clone(&update_manager);
+            let mut update_manager_g = update_manager.lock().unwrap();
+            update_manager_g.schedule(CONFIG.update.max_concurrent_tasks);
+            let task_r = update_manager_g.rx.try_recv();
+            drop(update_manager_g);
+
+            match task_r {
+                Ok(message) => {
+                    let app_manager = Arc::clone(&app_manager);
+                    let settings_manager = Arc::clone(&settings_manager);
+                    thread::spawn(move || {
+                        handle_update_control(
+                            message,
+                            update_manager_2,
+                            app_manager,
+                            settings_manager,
+                        );
+                    });
                 }
-            },
+                Err(e) => match e {
+                    TryRecvError::Empty => {}
+                    e => {
+                        debug!("get UpdateTaskControlMessage failed, err: {}", e)
+                    }
+                },
+            }
         }
     });
 }
+
+// queued or running, including waiting for confirmation. A paused task takes no slot, it is
+// replaced by a new task when resumed
+fn is_active(update_task: &UpdateTask) -> bool {
+    !matches!(
+        update_task.status,
+        UpdateTaskStatus::Paused
+            | UpdateTaskStatus::Canceled
+            | UpdateTaskStatus::Failed { .. }
+            | UpdateTaskStatus::Planned { .. }
+            | UpdateTaskStatus::Verified { .. }
+            | UpdateTaskStatus::Finished { .. }
+    )
+}
+
+// a task waiting for the user takes no slot, e.g. a background update would block the queue
+// until someone answers. Once confirmed it syncs even if the slots are taken by then.
+fn is_taking_slot(update_task: &UpdateTask) -> bool {
+    is_active(update_task)
+        && !matches!(
+            update_task.status,
+            UpdateTaskStatus::AwaitingConfirmation { .. }
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::application::update::update_manage::UpdateManager;
+    use crate::application::update::{Error, TaskPriority, UpdateTaskStatus};
+
+    fn add(
+        update_manager: &mut UpdateManager,
+        app_server_id: u64,
+        priority: TaskPriority,
+        data_path: &str,
+    ) -> Result<(), Error> {
+        let mut task = update_manager.create_task(app_server_id);
+        task.priority = priority;
+        task.data_path = data_path.to_string();
+        update_manager.add_task(task)
+    }
+
+    fn get_positions(update_manager: &UpdateManager, app_server_ids: &[u64]) -> Vec<usize> {
+        app_server_ids
+            .iter()
+            .map(|x| update_manager.get_queue_position(*x).unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut update_manager = UpdateManager::default();
+        add(&mut update_manager, 1, TaskPriority::Background, "a").unwrap();
+        add(&mut update_manager, 2, TaskPriority::Normal, "b").unwrap();
+        add(&mut update_manager, 3, TaskPriority::Normal, "c").unwrap();
+        assert_eq!(get_positions(&update_manager, &[1, 2, 3]), vec![3, 1, 2]);
+
+        assert!(matches!(
+            add(&mut update_manager, 2, TaskPriority::Normal, "d"),
+            Err(Error::DuplicateTask)
+        ));
+        assert!(matches!(
+            add(&mut update_manager, 4, TaskPriority::Normal, "a"),
+            Err(Error::DuplicateTask)
+        ));
+
+        // the moved task is Normal now, a later Normal task is queued behind it
+        update_manager.move_queued_task(1, true);
+        assert_eq!(get_positions(&update_manager, &[1, 2, 3]), vec![2, 1, 3]);
+        add(&mut update_manager, 5, TaskPriority::Normal, "e").unwrap();
+        assert_eq!(
+            get_positions(&update_manager, &[1, 2, 3, 5]),
+            vec![2, 1, 3, 4]
+        );
+
+        update_manager.schedule(2);
+        assert_eq!(update_manager.get_queue_position(2), None);
+        assert_eq!(update_manager.get_queue_position(1), None);
+        assert_eq!(get_positions(&update_manager, &[3, 5]), vec![1, 2]);
+        // both slots are taken
+        update_manager.schedule(2);
+        assert_eq!(get_positions(&update_manager, &[3, 5]), vec![1, 2]);
+
+        let id = update_manager
+            .get_update_task_by_app_server_id(2)
+            .unwrap()
+            .id;
+        update_manager.update_tasks.get_mut(&id).unwrap().status = UpdateTaskStatus::Failed {
+            space_shortage: None,
+        };
+        update_manager.schedule(2);
+        assert_eq!(get_positions(&update_manager, &[5]), vec![1]);
+        // a finished task does not block a new one
+        add(&mut update_manager, 2, TaskPriority::Normal, "b").unwrap();
+
+        // a task waiting for confirmation frees its slot, but is still running
+        let id = update_manager
+            .get_update_task_by_app_server_id(1)
+            .unwrap()
+            .id;
+        update_manager.update_tasks.get_mut(&id).unwrap().status =
+            UpdateTaskStatus::AwaitingConfirmation {
+                plan: Default::default(),
+            };
+        update_manager.schedule(2);
+        assert_eq!(update_manager.get_queue_position(5), None);
+        assert!(update_manager.is_update_processing(1).unwrap());
+        assert!(matches!(
+            add(&mut update_manager, 1, TaskPriority::Normal, "a"),
+            Err(Error::DuplicateTask)
+        ));
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let mut update_manager = UpdateManager::default();
+        add(&mut update_manager, 1, TaskPriority::Normal, "a").unwrap();
+        add(&mut update_manager, 2, TaskPriority::Normal, "b").unwrap();
+        update_manager.schedule(1);
+        assert_eq!(update_manager.get_queue_position(1), None);
+        assert_eq!(get_positions(&update_manager, &[2]), vec![1]);
+
+        // the paused task frees its slot
+        update_manager
+            .get_mut_update_task_by_app_server_id(1)
+            .unwrap()
+            .status = UpdateTaskStatus::Paused;
+        assert!(!update_manager.is_update_processing(1).unwrap());
+        update_manager.schedule(1);
+        assert_eq!(update_manager.get_queue_position(2), None);
+
+        // resumed as update::start_task does, the paused task is replaced by a queued one
+        let id = update_manager
+            .get_update_task_by_app_server_id(1)
+            .unwrap()
+            .id;
+        update_manager.remove_task(id).unwrap();
+        add(&mut update_manager, 1, TaskPriority::Normal, "a").unwrap();
+        assert_eq!(get_positions(&update_manager, &[1]), vec![1]);
+        update_manager
+            .get_mut_update_task_by_app_server_id(2)
+            .unwrap()
+            .status = UpdateTaskStatus::Finished {
+            finish_time: 0,
+            protected_files: 0,
+        };
+        update_manager.schedule(1);
+        assert_eq!(update_manager.get_queue_position(1), None);
+        assert!(update_manager.is_update_processing(1).unwrap());
+
+        // restored after a restart, it does not block a resume either
+        update_manager.add_paused_task(3);
+        assert!(!update_manager.is_update_processing(3).unwrap());
+    }
+}
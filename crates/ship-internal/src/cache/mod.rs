@@ -1,14 +1,25 @@
-use crate::scan;
+use crate::config::{CacheLinkMode, CONFIG};
+use lazy_static::lazy_static;
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::path::Path;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::UNIX_EPOCH;
 use std::{fs, io, time};
 use util::filepath;
+use util::hash::HashAlgorithm;
+
+lazy_static! {
+    // the cache db is one file, every read-modify-write of it is done under this lock, the
+    // SyncTasks of all running updates add to it in parallel
+    static ref CACHE_DB_MUTEX: Mutex<()> = Mutex::new(());
+}
+
+// cache files added in the same nanosecond by different workers get different names
+static CACHE_NAME_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug)]
 pub enum CacheError {
@@ -27,13 +38,58 @@ pub enum CacheError {
     ScanCacheFailed,
 }
 
-pub fn get_cache_file(hash_sum: &str) -> Option<CacheFile> {
+pub fn get_cache_file(hash_algorithm: HashAlgorithm, hash_sum: &str) -> Option<CacheFile> {
+    let mu = CACHE_DB_MUTEX.lock().unwrap();
     let cache_info = get_cache_info().ok()?;
-    let f = cache_info.files.get(hash_sum)?;
+    drop(mu);
+    let f = cache_info
+        .files
+        .get(&get_cache_key(hash_algorithm, hash_sum))?;
     return Some(f.clone());
 }
 
-pub fn add_to_cache<P: AsRef<Path>>(src_path: P, app_id: u64) -> Result<(), CacheError> {
+// a snapshot of the cache db, many files are looked up without reading the db for each
+#[derive(Default)]
+pub struct CachedFiles {
+    keys: HashSet<String>,
+}
+
+impl CachedFiles {
+    pub fn insert(&mut self, hash_algorithm: HashAlgorithm, hash_sum: &str) {
+        self.keys.insert(get_cache_key(hash_algorithm, hash_sum));
+    }
+
+    pub fn contains(&self, hash_algorithm: HashAlgorithm, hash_sum: &str) -> bool {
+        self.keys.contains(&get_cache_key(hash_algorithm, hash_sum))
+    }
+}
+
+pub fn get_cached_files() -> CachedFiles {
+    let mu = CACHE_DB_MUTEX.lock().unwrap();
+    let cache_info = get_cache_info().unwrap_or_else(|e| {
+        warn!("get cache info failed, err: {:?}", e);
+        CacheInfo::default()
+    });
+    drop(mu);
+    CachedFiles {
+        keys: cache_info.files.into_keys().collect(),
+    }
+}
+
+// md5 keys have no prefix, so the cache db written before other algorithms is still valid
+fn get_cache_key(hash_algorithm: HashAlgorithm, hash_sum: &str) -> String {
+    match hash_algorithm {
+        HashAlgorithm::Md5 => hash_sum.to_string(),
+        _ => format!("{}:{}", hash_algorithm, hash_sum),
+    }
+}
+
+pub fn add_to_cache<P: AsRef<Path>>(
+    src_path: P,
+    app_id: u64,
+    hash_algorithm: HashAlgorithm,
+    is_hard_link_allowed: bool,
+) -> Result<(), CacheError> {
     let cache_dir_path =
         get_update_cache_dir_path().map_err(|_| CacheError::GetUpdateCacheDirPathFailed)?;
     fs::create_dir_all(&cache_dir_path).map_err(|e| {
@@ -45,7 +101,13 @@ pub fn add_to_cache<P: AsRef<Path>>(src_path: P, app_id: u64) -> Result<(), Cach
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_nanos();
-    let cache_name = format!("{}", t);
+    // the algorithm is kept in the name, so the db can be generated again from the files
+    let cache_name = format!(
+        "{}-{}.{}",
+        t,
+        CACHE_NAME_COUNTER.fetch_add(1, Ordering::Relaxed),
+        hash_algorithm
+    );
     let dst_dir_path = Path::new(&cache_dir_path).join(app_id.to_string()).join(d);
     let r = fs::create_dir_all(&dst_dir_path);
     if let Err(e) = r {
@@ -54,23 +116,74 @@ pub fn add_to_cache<P: AsRef<Path>>(src_path: P, app_id: u64) -> Result<(), Cach
     }
 
     let dst_path = dst_dir_path.join(&cache_name);
-    debug!("before copy to cache");
-    save_cache_file(&src_path, &dst_path)?;
-    debug!("after copy to cache");
+    debug!("before link to cache");
+    link_or_copy(src_path.as_ref(), &dst_path, is_hard_link_allowed).map_err(|e| {
+        warn!("save cache file failed, err: {}", e);
+        CacheError::SaveCacheFileFailed
+    })?;
+    debug!("after link to cache");
     let cache_rel_path = dst_path
         .strip_prefix(&cache_dir_path)
         .map_err(|_| CacheError::CachePathError)?;
-    add_to_db(&dst_path, cache_rel_path).map_err(|e| {
+    add_to_db(&dst_path, cache_rel_path, hash_algorithm).map_err(|e| {
         warn!("add to db failed, err: {:?}", e);
         return e;
     })?;
     Ok(())
 }
 
-fn save_cache_file<P: AsRef<Path>, Q: AsRef<Path>>(
-    src_path: P,
-    dst_path: Q,
-) -> Result<(), CacheError> {
+// files are put into and taken from the cache this way, dst_path must not exist. A reflinked file
+// shares blocks until one of them is written, a hard linked file is the same file, see
+// CacheLinkMode. Removing a cache file only removes its link, the file in a data dir is kept.
+// A hard linked file shares its mode and mtime too, it is not allowed for files whose metadata is
+// set after, see metadata::is_set.
+pub fn link_or_copy(
+    src_path: &Path,
+    dst_path: &Path,
+    is_hard_link_allowed: bool,
+) -> io::Result<()> {
+    let link_mode = match CONFIG.update.cache_link {
+        CacheLinkMode::Auto if !is_hard_link_allowed => CacheLinkMode::Reflink,
+        x => x,
+    };
+    link_or_copy_by_mode(src_path, dst_path, link_mode)
+}
+
+fn link_or_copy_by_mode(
+    src_path: &Path,
+    dst_path: &Path,
+    link_mode: CacheLinkMode,
+) -> io::Result<()> {
+    // a failed reflink removes the dst, it must be its own
+    if fs::symlink_metadata(dst_path).is_ok() {
+        return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+    }
+    if link_mode != CacheLinkMode::Copy {
+        match reflink_copy::reflink(src_path, dst_path) {
+            Ok(_) => {
+                debug!("reflinked, src: {:?}, dst: {:?}", src_path, dst_path);
+                return Ok(());
+            }
+            Err(e) => {
+                debug!("reflink failed, src: {:?}, err: {}", src_path, e);
+                let _ = fs::remove_file(dst_path);
+            }
+        }
+    }
+    // fails across volumes
+    if link_mode == CacheLinkMode::Auto {
+        match fs::hard_link(src_path, dst_path) {
+            Ok(_) => {
+                debug!("hard linked, src: {:?}, dst: {:?}", src_path, dst_path);
+                return Ok(());
+            }
+            Err(e) => debug!("hard link failed, src: {:?}, err: {}", src_path, e),
+        }
+    }
+    copy_file(src_path, dst_path)
+}
+
+fn copy_file(src_path: &Path, dst_path: &Path) -> io::Result<()> {
     // on windows slow ?why
     // fs::copy(&src_path, &dst_path)
     //     .map_err(|e| {
@@ -80,7 +193,7 @@ fn save_cache_file<P: AsRef<Path>, Q: AsRef<Path>>(
 
     let max_capacity = 1024 * 1024;
 
-    let rf = fs::File::open(&src_path).map_err(|_| CacheError::SaveCacheFileFailed)?;
+    let rf = fs::File::open(src_path)?;
 
     let capacity = rf
         .metadata()
@@ -93,18 +206,42 @@ fn save_cache_file<P: AsRef<Path>, Q: AsRef<Path>>(
         .unwrap_or(max_capacity);
 
     let mut br = io::BufReader::with_capacity(capacity as usize, rf);
-    let wf = fs::File::create(&dst_path).map_err(|_| CacheError::SaveCacheFileFailed)?;
+    let wf = fs::File::create_new(dst_path)?;
     let mut bw = io::BufWriter::with_capacity(capacity as usize, wf);
-    io::copy(&mut br, &mut bw).map_err(|e| {
-        warn!("save cache file failed, err: {}", e);
-        return CacheError::SaveCacheFileFailed;
-    })?;
-    bw.flush().map_err(|_| CacheError::SaveCacheFileFailed)?;
+    io::copy(&mut br, &mut bw)?;
+    bw.flush()?;
     Ok(())
 }
 
-fn add_to_db<P: AsRef<Path>>(dst_path: P, rel_path: &Path) -> Result<(), CacheError> {
-    let hash_sum = util::hash::md5::md5_file(&dst_path).map_err(|_| CacheError::CalcHashFailed)?;
+// evicts a cache file, e.g. a hard linked file changed in place, a file in a data dir linked to
+// it is kept
+pub fn remove_cache_file(hash_algorithm: HashAlgorithm, hash_sum: &str) -> Result<(), CacheError> {
+    let mu = CACHE_DB_MUTEX.lock().unwrap();
+    let mut cache_info = get_cache_info()?;
+    let cache_file = match cache_info
+        .files
+        .remove(&get_cache_key(hash_algorithm, hash_sum))
+    {
+        None => return Ok(()),
+        Some(x) => x,
+    };
+    save_cache_info(cache_info)?;
+    drop(mu);
+    let p = Path::new(&get_update_cache_dir_path()?).join(&cache_file.relative_path);
+    if let Err(e) = fs::remove_file(&p) {
+        warn!("remove cache file failed, path: {:?}, err: {}", p, e);
+    }
+    Ok(())
+}
+
+fn add_to_db<P: AsRef<Path>>(
+    dst_path: P,
+    rel_path: &Path,
+    hash_algorithm: HashAlgorithm,
+) -> Result<(), CacheError> {
+    let hash_sum = hash_algorithm
+        .hash_file(&dst_path)
+        .map_err(|_| CacheError::CalcHashFailed)?;
     let cache_file = CacheFile::new(
         rel_path
             .to_str()
@@ -112,9 +249,13 @@ fn add_to_db<P: AsRef<Path>>(dst_path: P, rel_path: &Path) -> Result<(), CacheEr
             .to_string(),
         &hash_sum,
     );
+    let mu = CACHE_DB_MUTEX.lock().unwrap();
     let mut cache_info = get_cache_info()?;
-    cache_info.files.insert(hash_sum, cache_file);
+    cache_info
+        .files
+        .insert(get_cache_key(hash_algorithm, &hash_sum), cache_file);
     save_cache_info(cache_info)?;
+    drop(mu);
     Ok(())
 }
 
@@ -159,11 +300,15 @@ fn get_cache_info() -> Result<CacheInfo, CacheError> {
     Ok(ci)
 }
 
+// written to a temp file and renamed, a reader never sees a half-written db. The caller holds
+// CACHE_DB_MUTEX.
 fn save_cache_info(cache_info: CacheInfo) -> Result<(), CacheError> {
     let p = get_cache_db_file()?;
     let j = serde_json::to_string(&cache_info).map_err(|_| CacheError::SerializeCacheInfoFailed)?;
 
-    fs::write(p, j).map_err(|_| CacheError::SaveCacheDbFailed)?;
+    let tmp_path = format!("{}.tmp", p);
+    fs::write(&tmp_path, j).map_err(|_| CacheError::SaveCacheDbFailed)?;
+    fs::rename(&tmp_path, &p).map_err(|_| CacheError::SaveCacheDbFailed)?;
     Ok(())
 }
 
@@ -208,23 +353,119 @@ pub fn generate_cache_db() -> Result<(), CacheError> {
     let mut cache_info = CacheInfo::default();
     let p = get_update_cache_dir_path()?;
     fs::create_dir_all(&p).map_err(|_| CacheError::CreateCacheDirFailed)?;
-    let is_cancel = Arc::new(AtomicBool::new(false));
-    let cfi = scan::scan(&p, is_cancel).map_err(|_| CacheError::ScanCacheFailed)?;
-    for x in cfi.files {
-        let hash_sum = x.hash;
-        if !hash_sum.is_empty() {
-            cache_info
-                .files
-                .insert(hash_sum.clone(), CacheFile::new(x.relative_path, &hash_sum));
+    scan_cache_dir(&p, &mut cache_info)?;
+    let mu = CACHE_DB_MUTEX.lock().unwrap();
+    save_cache_info(cache_info)?;
+    drop(mu);
+    Ok(())
+}
+
+// every cache file is hashed with the algorithm in its name
+fn scan_cache_dir(cache_dir_path: &str, cache_info: &mut CacheInfo) -> Result<(), CacheError> {
+    for entry in walkdir::WalkDir::new(cache_dir_path).into_iter().flatten() {
+        if !entry.file_type().is_file() {
+            continue;
         }
+        let p = entry.path();
+        let hash_algorithm = match get_cache_file_hash_algorithm(p) {
+            None => {
+                warn!("unknown cache file, path: {:?}", p);
+                continue;
+            }
+            Some(x) => x,
+        };
+        let hash_sum = match hash_algorithm.hash_file(p) {
+            Err(e) => {
+                warn!("hash cache file failed, path: {:?}, err: {}", p, e);
+                continue;
+            }
+            Ok(x) => x,
+        };
+        let relative_path = p
+            .strip_prefix(cache_dir_path)
+            .map_err(|_| CacheError::CachePathError)?
+            .to_str()
+            .ok_or(CacheError::ConvertPathToStrFailed)?
+            .to_string();
+        cache_info.files.insert(
+            get_cache_key(hash_algorithm, &hash_sum),
+            CacheFile::new(relative_path, &hash_sum),
+        );
     }
-    save_cache_info(cache_info)?;
     Ok(())
 }
 
+// the extension of a cache file name is its hash algorithm, files added before other algorithms
+// have none and are md5
+fn get_cache_file_hash_algorithm(p: &Path) -> Option<HashAlgorithm> {
+    match p.extension() {
+        None => Some(HashAlgorithm::Md5),
+        Some(x) => HashAlgorithm::from_name(x.to_str()?),
+    }
+}
+
 pub fn try_generate_cache_db() -> Result<(), CacheError> {
     if is_regenerate_cache_db().unwrap_or(false) {
         generate_cache_db()?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use crate::cache::{get_cache_key, link_or_copy_by_mode, scan_cache_dir, CacheInfo};
+    use crate::config::CacheLinkMode;
+    use crate::test_util::TempDir;
+    use std::fs;
+    use util::hash::HashAlgorithm;
+
+    #[test]
+    fn test_link_or_copy() {
+        let temp_dir = TempDir::new("cache");
+        let src_path = temp_dir.path().join("src");
+        fs::write(&src_path, "cached").unwrap();
+
+        for (i, link_mode) in [
+            CacheLinkMode::Auto,
+            CacheLinkMode::Reflink,
+            CacheLinkMode::Copy,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let dst_path = temp_dir.path().join(i.to_string());
+            link_or_copy_by_mode(&src_path, &dst_path, link_mode).unwrap();
+            assert_eq!(fs::read_to_string(&dst_path).unwrap(), "cached");
+            // the dst must not exist
+            assert!(link_or_copy_by_mode(&src_path, &dst_path, link_mode).is_err());
+        }
+        // the src is kept, e.g. the cache file of a linked staged file
+        assert_eq!(fs::read_to_string(&src_path).unwrap(), "cached");
+    }
+
+    #[test]
+    fn test_scan_cache_dir() {
+        let temp_dir = TempDir::new("cache");
+        let dir_path = temp_dir.path().join("1").join("2024-01-01");
+        fs::create_dir_all(&dir_path).unwrap();
+        // added before other algorithms, and with sha256
+        fs::write(dir_path.join("100-0"), "old").unwrap();
+        fs::write(dir_path.join("100-1.sha256"), "new").unwrap();
+        fs::write(dir_path.join("100-2.unknown"), "other").unwrap();
+
+        let mut cache_info = CacheInfo::default();
+        scan_cache_dir(temp_dir.path().to_str().unwrap(), &mut cache_info).unwrap();
+        assert_eq!(cache_info.files.len(), 2);
+        for (hash_algorithm, content, name) in [
+            (HashAlgorithm::Md5, "old", "100-0"),
+            (HashAlgorithm::Sha256, "new", "100-1.sha256"),
+        ] {
+            let key = get_cache_key(
+                hash_algorithm,
+                &hash_algorithm.hash_bytes(content.as_bytes()),
+            );
+            let cache_file = cache_info.files.get(&key).unwrap();
+            assert!(cache_file.relative_path.ends_with(name));
+        }
+    }
+}
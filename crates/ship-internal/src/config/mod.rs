@@ -1,11 +1,13 @@
 mod error;
 
 use crate::config::error::Error;
+use crate::types::common::KeepRule;
 use config::builder::DefaultState;
 use config::ConfigBuilder;
 use lazy_static::lazy_static;
 use log::debug;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::Path;
 use util::filepath;
 
@@ -19,9 +21,18 @@ pub struct Config {
     pub language: String,
     pub server: Server,
     pub channel_code: String,
+    pub update: Update,
+    // by app code
+    #[serde(default)]
+    pub apps: HashMap<String, AppConfig>,
 }
 
 impl Config {
+    pub fn get_app_config(&self, app_code: &str) -> Option<&AppConfig> {
+        // keys are lowercased by the config crate
+        self.apps.get(&app_code.to_lowercase())
+    }
+
     pub fn print_config(&self) {
         debug!("log_level: {}", self.log_level);
         debug!("language: {}", self.language);
@@ -30,6 +41,31 @@ impl Config {
             self.server.address.to_address_string()
         );
         debug!("channel_code: {}", self.channel_code);
+        debug!("update.sync_workers: {}", self.update.sync_workers);
+        debug!(
+            "update.max_concurrent_tasks: {}",
+            self.update.max_concurrent_tasks
+        );
+        debug!("update.delta_sync: {}", self.update.delta_sync);
+        debug!("update.scan_workers: {}", self.update.scan_workers);
+        debug!("update.always_confirm: {}", self.update.always_confirm);
+        debug!("update.download_limit: {}", self.update.download_limit);
+        debug!(
+            "update.compressed_download: {}",
+            self.update.compressed_download
+        );
+        debug!("update.check_interval: {}", self.update.check_interval);
+        debug!(
+            "update.server_scan_timeout: {}",
+            self.update.server_scan_timeout
+        );
+        debug!("update.cache_link: {:?}", self.update.cache_link);
+        for (code, app) in &self.apps {
+            debug!("apps.{}.keep_rules: {:?}", code, app.keep_rules);
+            debug!("apps.{}.public_keys: {:?}", code, app.public_keys);
+            debug!("apps.{}.allow_unsigned: {}", code, app.allow_unsigned);
+            debug!("apps.{}.auto_update: {}", code, app.auto_update);
+        }
     }
 }
 
@@ -38,6 +74,52 @@ pub struct Server {
     pub address: Address,
 }
 
+#[derive(Deserialize)]
+pub struct Update {
+    pub sync_workers: usize,
+    // update tasks running at the same time, the others are queued
+    pub max_concurrent_tasks: usize,
+    pub delta_sync: bool,
+    pub scan_workers: usize,
+    pub always_confirm: bool,
+    // KiB/s, 0 means unlimited
+    pub download_limit: u64,
+    // ask data nodes for compressed files
+    pub compressed_download: bool,
+    // seconds between update checks of installed app servers, 0 disables them
+    pub check_interval: u64,
+    // seconds to wait for the server to finish scanning its files before an update fails
+    pub server_scan_timeout: u64,
+    // how files are put into and taken from the update cache
+    pub cache_link: CacheLinkMode,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheLinkMode {
+    // reflink, else hard link, else copy
+    Auto,
+    // reflink, else copy, a file is never shared with the cache
+    Reflink,
+    Copy,
+}
+
+#[derive(Deserialize, Default)]
+pub struct AppConfig {
+    // added to the keep rules from the server
+    #[serde(default)]
+    pub keep_rules: Vec<KeepRule>,
+    // hex of Ed25519 public keys, the file list from the server must be signed by one of them
+    #[serde(default)]
+    pub public_keys: Vec<String>,
+    // sync even if the file list is not signed
+    #[serde(default)]
+    pub allow_unsigned: bool,
+    // start updates found by the background check
+    #[serde(default)]
+    pub auto_update: bool,
+}
+
 #[derive(Deserialize)]
 pub struct Address {
     pub protocol: String,
@@ -87,4 +169,24 @@ fn set_default(b: ConfigBuilder<DefaultState>) -> ConfigBuilder<DefaultState> {
         .unwrap()
         .set_default("channel_code", "default")
         .unwrap()
+        .set_default("update.sync_workers", 4)
+        .unwrap()
+        .set_default("update.max_concurrent_tasks", 1)
+        .unwrap()
+        .set_default("update.delta_sync", true)
+        .unwrap()
+        .set_default("update.scan_workers", 0)
+        .unwrap()
+        .set_default("update.always_confirm", false)
+        .unwrap()
+        .set_default("update.download_limit", 0)
+        .unwrap()
+        .set_default("update.compressed_download", false)
+        .unwrap()
+        .set_default("update.check_interval", 600)
+        .unwrap()
+        .set_default("update.server_scan_timeout", 600)
+        .unwrap()
+        .set_default("update.cache_link", "auto")
+        .unwrap()
 }
@@ -4,6 +4,8 @@ pub mod cache;
 mod config;
 mod log;
 mod request;
+#[cfg(test)]
+mod test_util;
 mod types;
 pub mod version;
 
@@ -1,12 +1,17 @@
 use crate::request::error::Error;
-use crate::request::{get, get_full_url_by_server_address};
-use crate::types::common::Address;
+use crate::request::{get, get_full_url_by_server_address, read_text};
+use crate::types::common::{Address, ScanStatus};
 use log::debug;
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
 pub struct AppServerVo {
     pub data_nodes: Vec<DataNode>,
+    // last scan of the server files, not sent by older servers
+    #[serde(default)]
+    pub scan_status: Option<ScanStatus>,
+    #[serde(default)]
+    pub last_scan_finish_time: Option<i64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -19,7 +24,7 @@ pub fn get_app_server_info(server_address: &str) -> Result<AppServerVo, Error> {
     debug!("get_app_server_info");
     let url = get_full_url_by_server_address("/api/v1/info", server_address);
     let resp = get(&url)?;
-    let body = resp.text().map_err(|_| Error::ReadBodyError)?;
+    let body = read_text(resp)?;
     debug!("url: {}, body: {}", url, body);
     let data = serde_json::from_str::<AppServerVo>(&body).map_err(|e| {
         debug!("decode failed, err: {}", e);
@@ -1,5 +1,5 @@
 use crate::request::error::Error;
-use crate::request::{get, get_full_url_by_server_address};
+use crate::request::{get, get_full_url_by_server_address, read_text};
 use crate::types::banner::Banner;
 use log::debug;
 use serde::Deserialize;
@@ -13,7 +13,7 @@ pub fn get_banner(server_address: &str) -> Result<BannerVo, Error> {
     debug!("get_banner");
     let url = get_full_url_by_server_address("/api/v1/banner", server_address);
     let resp = get(&url)?;
-    let body = resp.text().map_err(|_| Error::ReadBodyError)?;
+    let body = read_text(resp)?;
     debug!("url: {}, body: {}", url, body);
     let data = serde_json::from_str::<BannerVo>(&body).map_err(|e| {
         debug!("decode failed, err: {}", e);
@@ -1,14 +1,20 @@
 use crate::request::error::Error;
-use crate::request::{get, get_full_url_by_server_address};
+use crate::request::{get, get_full_url_by_server_address, read_text};
 use crate::types::common::{FileType, ScanStatus};
 use log::debug;
 use serde::Deserialize;
 
+// hex of the detached Ed25519 signature of the body
+const SIGNATURE_HEADER: &str = "x-ship-signature";
+
 #[derive(Deserialize, Debug)]
 pub struct ServerFileInfoVo {
     #[serde(rename = "status")]
     pub scan_status: ScanStatus,
     pub last_scan_finish_time: i64,
+    // md5 if not set
+    #[serde(default)]
+    pub hash_algorithm: String,
     pub files: Vec<FileInfo>,
 }
 
@@ -19,18 +25,40 @@ pub struct FileInfo {
     pub file_type: FileType,
     pub size: u64,
     pub hash: String,
+    // unix permission bits, not sent by servers on Windows
+    #[serde(default)]
+    pub mode: Option<u32>,
+    // unix timestamp in seconds
+    #[serde(default)]
+    pub mtime: Option<i64>,
+}
+
+// the body is kept, the signature is over its exact bytes after decompression
+pub struct SignedServerFileInfo {
+    pub data: ServerFileInfoVo,
+    pub body: String,
+    pub signature: Option<String>,
 }
 
-pub fn get_file_info(server_address: &str) -> Result<ServerFileInfoVo, Error> {
+pub fn get_file_info(server_address: &str) -> Result<SignedServerFileInfo, Error> {
     debug!("get_file_info");
     let url = get_full_url_by_server_address("/api/v1/files", server_address);
     let resp = get(&url)?;
-    let body = resp.text().map_err(|_| Error::ReadBodyError)?;
+    let signature = resp
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.to_string());
+    let body = read_text(resp)?;
     debug!("url: {}, body: {}", url, body);
     let data = serde_json::from_str::<ServerFileInfoVo>(&body).map_err(|e| {
         debug!("decode failed, err: {}", e);
         Error::DecodeError
     })?;
-    debug!("data: {:?}", data);
-    Ok(data)
+    debug!("data: {:?}, signature: {:?}", data, signature);
+    Ok(SignedServerFileInfo {
+        data,
+        body,
+        signature,
+    })
 }
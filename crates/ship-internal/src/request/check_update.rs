@@ -1,5 +1,5 @@
 use crate::request::error::Error;
-use crate::request::{get, get_full_url};
+use crate::request::{get, get_full_url, read_text};
 use crate::version;
 use crate::version::version_manage::NewVersionInfo;
 use log::debug;
@@ -18,7 +18,7 @@ pub fn check_update() -> Result<CheckUpdateVo, Error> {
         version::VERSION_NO
     ));
     let resp = get(&url)?;
-    let body = resp.text().map_err(|_| Error::ReadBodyError)?;
+    let body = read_text(resp)?;
     debug!("url: {}, body: {}", url, body);
     let data = serde_json::from_str::<CheckUpdateVo>(&body).map_err(|e| {
         debug!("decode failed, err: {}", e);
@@ -0,0 +1,39 @@
+use crate::request::error::Error;
+use crate::request::{get, get_full_url_by_server_address, read_text};
+use log::debug;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct BlockChecksumsVo {
+    pub block_size: u64,
+    pub blocks: Vec<BlockChecksum>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlockChecksum {
+    pub weak: u32,
+    pub strong: String,
+}
+
+pub fn get_block_checksums(
+    data_node_address: &str,
+    relative_path: &str,
+) -> Result<BlockChecksumsVo, Error> {
+    debug!("get_block_checksums");
+    let url = get_full_url_by_server_address(
+        &format!("{}?file={}", "/api/v1/blocks", relative_path),
+        data_node_address,
+    );
+    let resp = get(&url)?;
+    if !resp.status().is_success() {
+        debug!("url: {}, status: {}", url, resp.status());
+        return Err(Error::RequestError);
+    }
+    let body = read_text(resp)?;
+    debug!("url: {}, body length: {}", url, body.len());
+    let data = serde_json::from_str::<BlockChecksumsVo>(&body).map_err(|e| {
+        debug!("decode failed, err: {}", e);
+        Error::DecodeError
+    })?;
+    Ok(data)
+}
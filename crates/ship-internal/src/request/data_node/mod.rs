@@ -0,0 +1 @@
+pub mod block_checksum;
@@ -1,5 +1,5 @@
 use crate::request::error::Error;
-use crate::request::{get, get_full_url};
+use crate::request::{get, get_full_url, read_text};
 use crate::types::common::Address;
 use log::debug;
 use serde::Deserialize;
@@ -23,7 +23,7 @@ pub fn get_app_servers(app_id: u64) -> Result<AppServersVo, Error> {
     debug!("get_app_servers");
     let url = get_full_url(&format!("{}?app_id={}", "/api/v1/app_servers", app_id));
     let resp = get(&url)?;
-    let body = resp.text().map_err(|_| Error::ReadBodyError)?;
+    let body = read_text(resp)?;
     debug!("url: {}, body: {}", url, body);
     let data = serde_json::from_str::<AppServersVo>(&body).map_err(|e| {
         debug!("decode failed, err: {}", e);
@@ -1,6 +1,6 @@
 use crate::request::error::Error;
-use crate::request::{get, get_full_url};
-use crate::types::common::Launch;
+use crate::request::{get, get_full_url, read_text};
+use crate::types::common::{KeepRule, Launch};
 use log::debug;
 use serde::Deserialize;
 
@@ -17,13 +17,15 @@ pub struct App {
     pub dir_name: String,
     pub priority: i64,
     pub launch: Launch,
+    #[serde(default)]
+    pub keep_rules: Vec<KeepRule>,
 }
 
 pub fn get_apps() -> Result<AppsVo, Error> {
     debug!("get_apps");
     let url = get_full_url("/api/v1/apps");
     let resp = get(&url)?;
-    let body = resp.text().map_err(|_| Error::ReadBodyError)?;
+    let body = read_text(resp)?;
     debug!("url: {}, body: {}", url, body);
     let data = serde_json::from_str::<AppsVo>(&body).map_err(|e| {
         debug!("decode failed, err: {}", e);
@@ -1,14 +1,26 @@
 use crate::config::CONFIG;
 use crate::request::error::Error;
 use crate::version;
+use lazy_static::lazy_static;
+use reqwest::header;
+use std::io::Read;
 use std::time::Duration;
+use util::compress;
+use util::rate_limit::RateLimiter;
 
 pub mod app_server;
 pub mod check_update;
+pub mod data_node;
 mod error;
 pub mod get_app_servers;
 pub mod get_apps;
 
+lazy_static! {
+    // shared by all downloads, so that the limit is for the whole program
+    pub static ref DOWNLOAD_RATE_LIMITER: RateLimiter =
+        RateLimiter::new(CONFIG.update.download_limit * 1024);
+}
+
 fn get_full_url(path: &str) -> String {
     let address = CONFIG.server.address.to_address_string();
     format!("{}{}", address, path)
@@ -30,6 +42,21 @@ fn get(url: &str) -> Result<reqwest::blocking::Response, Error> {
         .header("version_no", version_no)
         .header("version_text", version_text)
         .header("channel_code", channel_code)
+        .header(header::ACCEPT_ENCODING, compress::ACCEPT_ENCODING)
         .send()
         .map_err(|_| Error::RequestError)
 }
+
+// body is decompressed by Content-Encoding
+fn read_text(resp: reqwest::blocking::Response) -> Result<String, Error> {
+    let content_encoding = resp
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.to_string());
+    let mut body = "".to_string();
+    compress::decode_reader(content_encoding.as_deref(), resp)
+        .and_then(|mut x| x.read_to_string(&mut body))
+        .map_err(|_| Error::ReadBodyError)?;
+    Ok(body)
+}
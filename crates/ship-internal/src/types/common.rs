@@ -1,6 +1,7 @@
 use crate::request;
 use serde::Deserialize;
-use serde_repr::Deserialize_repr;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use util::hash::HashAlgorithm;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Address {
@@ -32,7 +33,7 @@ pub enum ScanStatus {
     Completed = 40,
 }
 
-#[derive(PartialEq, Deserialize_repr, Clone, Debug)]
+#[derive(PartialEq, Serialize_repr, Deserialize_repr, Clone, Debug)]
 #[repr(i8)]
 pub enum FileType {
     Unknown = 0,
@@ -58,26 +59,43 @@ pub struct FileInfo {
     pub file_type: FileType,
     pub size: u64,
     pub hash: String,
+    pub hash_algorithm: HashAlgorithm,
+    // unix permission bits and modification time in seconds, see update::metadata
+    pub mode: Option<u32>,
+    pub mtime: Option<i64>,
 }
 
 impl FileInfo {
-    pub fn new(relative_path: &str, file_type: FileType, size: u64, hash: &str) -> Self {
+    pub fn new(
+        relative_path: &str,
+        file_type: FileType,
+        size: u64,
+        hash: &str,
+        hash_algorithm: HashAlgorithm,
+    ) -> Self {
         Self {
             relative_path: relative_path.to_string(),
             file_type,
             size,
             hash: hash.to_string(),
+            hash_algorithm,
+            mode: None,
+            mtime: None,
         }
     }
-}
 
-impl From<&request::app_server::file_info::FileInfo> for FileInfo {
-    fn from(value: &request::app_server::file_info::FileInfo) -> Self {
+    fn from_vo(
+        value: &request::app_server::file_info::FileInfo,
+        hash_algorithm: HashAlgorithm,
+    ) -> Self {
         Self {
             relative_path: value.relative_path.clone(),
             file_type: value.file_type.clone(),
             size: value.size,
             hash: value.hash.clone(),
+            hash_algorithm,
+            mode: value.mode,
+            mtime: value.mtime,
         }
     }
 }
@@ -86,16 +104,28 @@ impl From<&request::app_server::file_info::FileInfo> for FileInfo {
 pub struct ServerFileInfo {
     pub scan_status: ScanStatus,
     pub last_scan_finish_time: i64,
+    pub hash_algorithm: HashAlgorithm,
     pub files: Vec<FileInfo>,
 }
 
-impl From<&request::app_server::file_info::ServerFileInfoVo> for ServerFileInfo {
-    fn from(value: &request::app_server::file_info::ServerFileInfoVo) -> Self {
-        Self {
+impl TryFrom<&request::app_server::file_info::ServerFileInfoVo> for ServerFileInfo {
+    type Error = String;
+
+    fn try_from(value: &request::app_server::file_info::ServerFileInfoVo) -> Result<Self, String> {
+        let hash_algorithm = HashAlgorithm::from_name(&value.hash_algorithm).ok_or(format!(
+            "unsupported hash algorithm: {}",
+            value.hash_algorithm
+        ))?;
+        Ok(Self {
             scan_status: value.scan_status.clone(),
             last_scan_finish_time: value.last_scan_finish_time,
-            files: value.files.iter().map(|x| FileInfo::from(x)).collect(),
-        }
+            hash_algorithm,
+            files: value
+                .files
+                .iter()
+                .map(|x| FileInfo::from_vo(x, hash_algorithm))
+                .collect(),
+        })
     }
 }
 
@@ -131,6 +161,15 @@ impl From<&request::app_server::app_server_info::DataNode> for DataNode {
     }
 }
 
+#[derive(Deserialize, Clone, Debug)]
+pub struct KeepRule {
+    // glob relative to the data path, e.g. saves/** or *.cfg
+    pub pattern: String,
+    // also skip updating matched files which exist locally
+    #[serde(default)]
+    pub no_overwrite: bool,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct Launch {
     pub steps: Vec<LaunchStep>,
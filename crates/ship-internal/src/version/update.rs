@@ -1,3 +1,4 @@
+use crate::request::DOWNLOAD_RATE_LIMITER;
 use crate::version::version_manage::VersionManager;
 use crate::{request, version};
 use log::{debug, info, warn};
@@ -9,6 +10,7 @@ use std::sync::mpsc::Sender;
 use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 use std::{env, fs, io, process, thread};
+use util::rate_limit::RateLimitedReader;
 
 #[derive(Default)]
 pub struct Progress {
@@ -135,7 +137,7 @@ fn do_download<P: AsRef<Path>>(
     file_path: P,
     tx: Sender<UpdateStatus>,
 ) -> Result<(), Error> {
-    let mut resp = reqwest::blocking::get(download_url).map_err(|_| Error::DownloadFailed)?;
+    let resp = reqwest::blocking::get(download_url).map_err(|_| Error::DownloadFailed)?;
     let f = fs::File::create(file_path).map_err(|_| Error::CreateFileFailed)?;
     let mut writer = io::BufWriter::new(f);
     let mut buf = [0; 1024 * 1024];
@@ -143,6 +145,7 @@ fn do_download<P: AsRef<Path>>(
     let total = resp.content_length().unwrap();
     debug!("content-length: {}", total);
     let value_1 = Arc::clone(&value);
+    let mut reader = RateLimitedReader::new(resp, &DOWNLOAD_RATE_LIMITER);
     let is_stop = Arc::new(AtomicBool::new(false));
     let is_stop_1 = Arc::clone(&is_stop);
     thread::spawn(move || loop {
@@ -158,7 +161,7 @@ fn do_download<P: AsRef<Path>>(
         });
     });
     loop {
-        let n = resp
+        let n = reader
             .read(&mut buf)
             .map_err(|_| Error::ReadDownloadContentFailed)?;
         if n == 0 {
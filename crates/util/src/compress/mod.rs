@@ -0,0 +1,55 @@
+use std::io;
+use std::io::Read;
+
+// value of Accept-Encoding for the encodings decode_reader supports
+pub const ACCEPT_ENCODING: &str = "zstd, gzip";
+
+// decompress r by the Content-Encoding of a response, r is returned as is when there is none
+pub fn decode_reader<'a, R: Read + 'a>(
+    content_encoding: Option<&str>,
+    r: R,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let encoding = content_encoding.unwrap_or("").trim().to_lowercase();
+    match encoding.as_str() {
+        "" | "identity" => Ok(Box::new(r)),
+        "gzip" | "x-gzip" => Ok(Box::new(flate2::read::MultiGzDecoder::new(r))),
+        "zstd" => Ok(Box::new(zstd::stream::read::Decoder::new(r)?)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unsupported content encoding: {}", encoding),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compress::decode_reader;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_decode_reader() {
+        let data = "ship ".repeat(10000).into_bytes();
+
+        let mut gzip_encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip_encoder.write_all(&data).unwrap();
+        let gzip_data = gzip_encoder.finish().unwrap();
+        let zstd_data = zstd::stream::encode_all(data.as_slice(), 0).unwrap();
+
+        let cases = [
+            (None, data.clone()),
+            (Some("identity"), data.clone()),
+            (Some("gzip"), gzip_data),
+            (Some("zstd"), zstd_data),
+        ];
+        for (encoding, encoded) in cases {
+            let mut decoded = vec![];
+            decode_reader(encoding, encoded.as_slice())
+                .unwrap()
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, data, "encoding: {:?}", encoding);
+        }
+        assert!(decode_reader(Some("br"), data.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use sha2::Digest;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{fmt, io};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub enum HashAlgorithm {
+    // manifests without an algorithm tag use md5
+    #[default]
+    Md5,
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "" | "md5" => Some(Self::Md5),
+            "sha256" | "sha-256" => Some(Self::Sha256),
+            "blake3" => Some(Self::Blake3),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            Self::Md5 => Hasher::Md5(md5::Context::new()),
+            Self::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Self::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn hash_bytes(&self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub fn hash_file<P: AsRef<Path>>(&self, path: P) -> Result<String, io::Error> {
+        self.hash_file_cancelable(path, &AtomicBool::new(false), None)
+    }
+
+    // on_progress is called with the bytes of each chunk read
+    pub fn hash_file_cancelable<P: AsRef<Path>>(
+        &self,
+        path: P,
+        is_cancel: &AtomicBool,
+        on_progress: Option<&dyn Fn(u64)>,
+    ) -> Result<String, io::Error> {
+        let mut f = File::open(path)?;
+        let mut hasher = self.hasher();
+        let mut buf = vec![0; 256 * 1024];
+        loop {
+            if is_cancel.load(Ordering::Relaxed) {
+                return Err(io::ErrorKind::Other.into());
+            }
+
+            let n = f.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            if let Some(f) = on_progress {
+                f(n as u64);
+            }
+        }
+        Ok(hasher.finalize())
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// streaming hasher, the result is a lowercase hex string
+pub enum Hasher {
+    Md5(md5::Context),
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(x) => x.consume(data),
+            Hasher::Sha256(x) => x.update(data),
+            Hasher::Blake3(x) => {
+                x.update(data);
+            }
+        }
+    }
+
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Md5(x) => format!("{:x}", x.compute()),
+            Hasher::Sha256(x) => format!("{:x}", x.finalize()),
+            Hasher::Blake3(x) => x.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hash::hasher::HashAlgorithm;
+    use crate::hash::md5::md5_file;
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    #[test]
+    fn test_known_hash() {
+        let cases = [
+            (HashAlgorithm::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+            (
+                HashAlgorithm::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashAlgorithm::Blake3,
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+        ];
+        for (algorithm, expected) in cases {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"a");
+            hasher.update(b"bc");
+            assert_eq!(hasher.finalize(), expected, "algorithm: {}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_md5_file_eq() {
+        let p = "README.md";
+        let a = md5_file(p).unwrap();
+        let b = HashAlgorithm::Md5.hash_file(p).unwrap();
+        assert_eq!(a, b, "md5_file->a: {}, hash_file->b: {}", a, b);
+    }
+
+    #[test]
+    fn test_hash_file_progress() {
+        let p = "README.md";
+        let hashed_bytes = AtomicU64::new(0);
+        let on_progress = |n| {
+            hashed_bytes.fetch_add(n, Ordering::Relaxed);
+        };
+        let h = HashAlgorithm::Sha256
+            .hash_file_cancelable(p, &AtomicBool::new(false), Some(&on_progress))
+            .unwrap();
+        assert_eq!(h, HashAlgorithm::Sha256.hash_file(p).unwrap());
+        assert_eq!(
+            hashed_bytes.load(Ordering::Relaxed),
+            fs::metadata(p).unwrap().len()
+        );
+        assert!(HashAlgorithm::Sha256
+            .hash_file_cancelable(p, &AtomicBool::new(true), None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(HashAlgorithm::from_name(""), Some(HashAlgorithm::Md5));
+        assert_eq!(
+            HashAlgorithm::from_name("SHA256"),
+            Some(HashAlgorithm::Sha256)
+        );
+        assert_eq!(
+            HashAlgorithm::from_name("blake3"),
+            Some(HashAlgorithm::Blake3)
+        );
+        assert_eq!(HashAlgorithm::from_name("crc32"), None);
+    }
+}
@@ -1 +1,5 @@
+mod hasher;
 pub mod md5;
+pub mod rolling;
+
+pub use hasher::{HashAlgorithm, Hasher};
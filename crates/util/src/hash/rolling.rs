@@ -0,0 +1,63 @@
+// rsync style weak checksum, a = sum(x[i]), b = sum((len - i) * x[i]), both mod 2^16
+#[derive(Debug, Clone)]
+pub struct RollingChecksum {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl RollingChecksum {
+    pub fn new(data: &[u8]) -> Self {
+        let len = data.len() as u32;
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        for (i, x) in data.iter().enumerate() {
+            a = a.wrapping_add(*x as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(*x as u32));
+        }
+        Self {
+            a: a & 0xffff,
+            b: b & 0xffff,
+            len,
+        }
+    }
+
+    // move the window forward by one byte
+    pub fn roll(&mut self, out_byte: u8, in_byte: u8) {
+        self.a = self
+            .a
+            .wrapping_sub(out_byte as u32)
+            .wrapping_add(in_byte as u32)
+            & 0xffff;
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out_byte as u32))
+            .wrapping_add(self.a)
+            & 0xffff;
+    }
+
+    pub fn value(&self) -> u32 {
+        self.a | (self.b << 16)
+    }
+}
+
+pub fn rolling_checksum(data: &[u8]) -> u32 {
+    RollingChecksum::new(data).value()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hash::rolling::{rolling_checksum, RollingChecksum};
+
+    #[test]
+    fn test_roll_eq() {
+        let data: Vec<u8> = (0..4096).map(|x| (x * 31 % 251) as u8).collect();
+        let block_size = 64;
+        let mut c = RollingChecksum::new(&data[..block_size]);
+        for i in 1..data.len() - block_size {
+            c.roll(data[i - 1], data[i + block_size - 1]);
+            let expected = rolling_checksum(&data[i..i + block_size]);
+            assert_eq!(c.value(), expected, "offset: {}", i);
+        }
+    }
+}
@@ -1,7 +1,9 @@
 #![allow(dead_code)]
 
+pub mod compress;
 pub mod convert;
 pub mod filepath;
 pub mod fs;
 pub mod hash;
+pub mod rate_limit;
 pub mod time;
@@ -0,0 +1,139 @@
+use std::io;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// the bucket holds at most this part of a second of traffic, so that short bursts are allowed
+// but the rate stays smooth
+const BURST_DIVISOR: u64 = 10;
+// reads are split into chunks of at least this size when limited
+const MIN_CHUNK_SIZE: u64 = 4 * 1024;
+
+// token bucket shared by all downloads, a limit of 0 means unlimited
+pub struct RateLimiter {
+    // bytes per second
+    limit: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    // may be negative, the taker of the debt waits for it
+    tokens: f64,
+    last_refill_time: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit: AtomicU64::new(limit),
+            bucket: Mutex::new(Bucket {
+                tokens: get_capacity(limit),
+                last_refill_time: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn get_limit(&self) -> u64 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    // takes effect for the next read of every download
+    pub fn set_limit(&self, limit: u64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.limit.store(limit, Ordering::Relaxed);
+        bucket.tokens = get_capacity(limit);
+        bucket.last_refill_time = Instant::now();
+    }
+
+    // size of a read that keeps the wait for its tokens short
+    pub fn get_chunk_size(&self) -> usize {
+        match self.get_limit() {
+            0 => usize::MAX,
+            limit => (limit / BURST_DIVISOR).max(MIN_CHUNK_SIZE) as usize,
+        }
+    }
+
+    // take n bytes from the bucket, blocks until they are available
+    pub fn acquire(&self, n: u64) {
+        let limit = self.get_limit();
+        if limit == 0 || n == 0 {
+            return;
+        }
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill_time).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit as f64).min(get_capacity(limit));
+        bucket.last_refill_time = now;
+        bucket.tokens -= n as f64;
+        let wait = if bucket.tokens < 0.0 {
+            Duration::from_secs_f64(-bucket.tokens / limit as f64)
+        } else {
+            Duration::ZERO
+        };
+        drop(bucket);
+
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+fn get_capacity(limit: u64) -> f64 {
+    (limit / BURST_DIVISOR).max(MIN_CHUNK_SIZE) as f64
+}
+
+// every read of inner takes its bytes from the limiter
+pub struct RateLimitedReader<'a, R: Read> {
+    inner: R,
+    limiter: &'a RateLimiter,
+}
+
+impl<'a, R: Read> RateLimitedReader<'a, R> {
+    pub fn new(inner: R, limiter: &'a RateLimiter) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<'a, R: Read> Read for RateLimitedReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf.len().min(self.limiter.get_chunk_size());
+        let n = self.inner.read(&mut buf[..max])?;
+        self.limiter.acquire(n as u64);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rate_limit::{RateLimitedReader, RateLimiter};
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = RateLimiter::new(1024 * 1024);
+        let data = vec![0u8; 512 * 1024];
+        let mut reader = RateLimitedReader::new(data.as_slice(), &limiter);
+        let mut buf = vec![0u8; 1024 * 1024];
+        let start = Instant::now();
+        let mut total = 0;
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(n <= limiter.get_chunk_size());
+            total += n;
+        }
+        assert_eq!(total, data.len());
+        // the first chunk is free, the rest of the 512K takes about 0.4s at 1M/s
+        assert!(start.elapsed() >= Duration::from_millis(350));
+
+        limiter.set_limit(0);
+        let start = Instant::now();
+        limiter.acquire(1024 * 1024 * 1024);
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
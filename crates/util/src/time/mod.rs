@@ -13,3 +13,13 @@ pub fn format_timestamp_to_date(timestamp: i64) -> String {
     let st = t.format("%Y-%m-%d");
     st.to_string()
 }
+
+// e.g. 1:02:03 or 02:03
+pub fn format_duration(seconds: u64) -> String {
+    let (h, m, s) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{:02}:{:02}", m, s)
+    }
+}